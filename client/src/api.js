@@ -2,36 +2,54 @@ export class HttpError extends Error {
   /**
    * @param {number} status
    * @param {string} statusText
+   * @param {{ code: string, message: string, context: object }} [body]
    */
-  constructor(status, statusText) {
-    super(statusText);
+  constructor(status, statusText, body) {
+    super(body ? body.message : statusText);
     this.statusText = statusText;
     this.status = status;
+    this.code = body && body.code;
+    this.context = body ? body.context : {};
+  }
+
+  /**
+   * @param {Response} response
+   */
+  static async from(response) {
+    const body = await response.json().catch(() => undefined);
+    return new HttpError(response.status, response.statusText, body);
   }
 }
 
 /**
  * @param {Response} response
  */
-function getResponseData(response) {
+async function getResponseData(response) {
   if (response.ok) {
     return response.json();
   } else {
-    throw new HttpError(response.status, response.statusText);
+    throw await HttpError.from(response);
   }
 }
 
 export default class API {
-  static async analyze(path, params) {
-    const resp = await fetch(`/analyze?path=${path}&dist=${params.distance}&hashType=${params.hashType}&hashSize=${params.hashSize}`, {
+  static async analyze(paths, params) {
+    const resp = await fetch('/analyze', {
       method: 'POST',
+      headers: { 'Content-Type': 'application/json' },
+      body: JSON.stringify({
+        paths,
+        dist: Number(params.distance),
+        hashType: params.hashType,
+        hashSize: Number(params.hashSize),
+      }),
     });
 
     return getResponseData(resp);
   }
 
-  static async poll(taskId) {
-    const resp = await fetch(`/poll?taskId=${taskId}`);
+  static async poll(taskId, offset = 0, limit = 100) {
+    const resp = await fetch(`/poll?taskId=${taskId}&sort=date&offset=${offset}&limit=${limit}`);
     return getResponseData(resp);
   }
 
@@ -70,12 +88,12 @@ export default class API {
     });
 
     if (!resp.ok) {
-      throw new HttpError(resp.status, resp.statusText);
+      throw await HttpError.from(resp);
     }
   }
 
-  static async listDeleted() {
-    const resp = await fetch(`/deleted`);
+  static async listDeleted(offset, limit) {
+    const resp = await fetch(`/deleted?offset=${offset}&limit=${limit}`);
     return getResponseData(resp);
   }
 }
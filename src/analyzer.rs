@@ -1,126 +1,500 @@
 use eyre::Result;
+use image::DynamicImage;
 use image_hasher::{Hasher, ImageHash, HasherConfig, HashAlg};
-use rayon::prelude::*;
-use std::fs::{self, DirEntry};
+use rayon::{prelude::*, ThreadPool};
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{mpsc, Mutex};
+use std::thread;
 use std::time::SystemTime;
 use tokio::sync::watch;
 
-use crate::cache::Cache;
+use crate::cache::{Cache, Claim, ClaimGuard};
+use crate::error::Error;
 use crate::disjoint_set;
+use crate::histogram::{self, Histogram};
+use crate::index::HashIndex;
+use crate::regions;
+use crate::scanner::{self, Root, ScanOptions};
+use crate::snapshot::{Snapshot, Snapshots};
+use crate::verifier;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct FileInfo {
-    path: PathBuf,
-    size: u64,
-    date: u64,
+    pub path: PathBuf,
+    pub size: u64,
+    pub date: u64,
+    /// last modification time
+    pub modified: u64,
+    /// the file comes from the reference collection rather than from the analyzed folder
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reference: bool,
+    /// the analyzed root folder the file was found in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root: Option<PathBuf>,
+    /// other paths of the same file: hardlinks, or symlinks when those are followed
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<PathBuf>,
 }
 
 impl FileInfo {
-    pub fn from_entry(entry: DirEntry) -> Result<Self> {
-        let metadata = entry.metadata()?;
+    pub fn from_metadata(path: PathBuf, metadata: &fs::Metadata) -> Result<Self> {
         let size = metadata.len();
         let ctime = metadata.created()?;
         let ctime = ctime.duration_since(SystemTime::UNIX_EPOCH)?;
+        let mtime = metadata.modified()?;
+        let mtime = mtime.duration_since(SystemTime::UNIX_EPOCH)?;
         Ok(Self {
-            path: entry.path(),
+            path,
             size,
             date: ctime.as_millis() as u64,
+            modified: mtime.as_millis() as u64,
+            reference: false,
+            root: None,
+            links: Vec::new(),
         })
     }
+
+    pub fn from_path(path: &Path) -> Result<Self> {
+        Self::from_metadata(path.to_owned(), &fs::metadata(path)?)
+    }
+
+    pub fn stamp(&self) -> Stamp {
+        (self.size, self.modified)
+    }
+}
+
+/// size and modification time, cached data is outdated once these change
+pub type Stamp = (u64, u64);
+
+type Stamped<T> = (Stamp, T);
+
+/// ignores values cached before the file was modified
+fn unless_stale<T>(cached: Result<Option<Stamped<T>>>, stamp: Stamp) -> Option<T> {
+    cached.ok()?.and_then(|(s, v)| (s == stamp).then_some(v))
+}
+
+fn is_fresh<T>(stamp: Stamp) -> impl FnOnce(&Stamped<T>) -> bool {
+    move |(s, _)| *s == stamp
+}
+
+/// blocks until another analysis is done computing a feature
+type Wait = Box<dyn FnOnce() + Send>;
+
+/// returns the cached feature, or the claim to compute it; nothing is returned but the
+/// wait for it if another analysis is computing the feature
+fn claim_feature<K, V>(cache: &Cache<K, Stamped<V>>, key: K, stamp: Stamp, waits: &mut Vec<Wait>) -> (Option<V>, Option<ClaimGuard<K, Stamped<V>>>)
+where
+    K: Eq + std::hash::Hash + std::fmt::Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    match cache.claim(key, is_fresh(stamp)) {
+        Ok(Claim::Cached((_, value))) => (Some(value), None),
+        Ok(Claim::Claimed(guard)) => (None, Some(guard)),
+        Ok(Claim::Pending(waiter)) => {
+            // a claim passed back is released right away, the file is looked up again
+            waits.push(Box::new(move || drop(waiter.wait())));
+            (None, None)
+        }
+        Err(err) => {
+            tracing::error!("unable to look the cache up: {:?}", err);
+            (None, None)
+        }
+    }
 }
 
-fn list_dir_rec(files: &mut Vec<FileInfo>, dir: &Path) -> Result<()> {
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_dir() {
-            if list_dir_rec(files, &path).is_err() {
-                tracing::error!("error reading folder content {:?}", path);
+/// claims on the features missing from the caches, released unless published
+#[derive(Default)]
+struct Claims {
+    hash: Option<ClaimGuard<CacheKey, Stamped<ImageHash>>>,
+    regions: Option<ClaimGuard<CacheKey, Stamped<Vec<ImageHash>>>>,
+    histogram: Option<ClaimGuard<PathBuf, Stamped<Histogram>>>,
+}
+
+enum Hashed {
+    /// None if the image couldn't be read
+    Done(Option<(FileInfo, Features)>),
+    /// other analyses are computing some of the features
+    Deferred(FileInfo, Vec<Wait>),
+}
+
+#[derive(Debug, Clone, Default)]
+struct Features {
+    hash: Option<ImageHash>,
+    regions: Option<Vec<ImageHash>>,
+    histogram: Option<Histogram>,
+}
+
+impl Features {
+    fn is_complete(&self, req: &AnalyzeRequest) -> bool {
+        let shape = match req.mode {
+            Mode::Global => self.hash.is_some(),
+            Mode::Regions => self.regions.is_some(),
+            Mode::Histogram => true,
+        };
+        shape && (!req.uses_color() || self.histogram.is_some())
+    }
+
+    fn compute(&mut self, req: &AnalyzeRequest, hasher: &Hasher, image: &DynamicImage) {
+        match req.mode {
+            Mode::Global => {
+                self.hash.get_or_insert_with(|| hasher.hash_image(image));
             }
-        } else if let Some(ext) = path.extension() {
-            if ext.eq_ignore_ascii_case("jpg")
-                || ext.eq_ignore_ascii_case("jpeg")
-                || ext.eq_ignore_ascii_case("png")
-            {
-                let info = FileInfo::from_entry(entry)?;
-                files.push(info);
+            Mode::Regions => {
+                self.regions.get_or_insert_with(|| regions::hash_regions(hasher, image));
             }
+            Mode::Histogram => {}
+        }
+
+        if req.uses_color() {
+            self.histogram.get_or_insert_with(|| histogram::compute(image));
         }
     }
 
-    Ok(())
+    /// the hash of the whole image, regions start with it
+    fn whole_hash(&self) -> Option<&ImageHash> {
+        self.hash.as_ref().or_else(|| self.regions.as_ref()?.first())
+    }
+
+    fn is_similar(&self, other: &Self, req: &AnalyzeRequest) -> bool {
+        let shape = match (req.mode, self, other) {
+            (Mode::Global, Self { hash: Some(h1), .. }, Self { hash: Some(h2), .. }) => {
+                h1.dist(h2) <= req.dist
+            }
+            (Mode::Regions, Self { regions: Some(r1), .. }, Self { regions: Some(r2), .. }) => {
+                regions::shared_regions(r1, r2, req.dist) >= req.shared_regions
+            }
+            (Mode::Histogram, ..) => true,
+            _ => false,
+        };
+
+        if !shape || !req.uses_color() {
+            return shape;
+        }
+
+        match (&self.histogram, &other.histogram) {
+            (Some(c1), Some(c2)) => histogram::distance(c1, c2) <= req.color_threshold(),
+            _ => false,
+        }
+    }
 }
 
-pub fn list_dir(dir: &Path) -> Result<Vec<FileInfo>> {
-    let mut files = Vec::new();
-    list_dir_rec(&mut files, dir)?;
-    Ok(files)
+type Hashes = Vec<(FileInfo, Features)>;
+
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct Progress {
+    /// image files found so far
+    pub discovered: usize,
+    /// image files hashed so far
+    pub processed: usize,
+    /// the traversal is over, `discovered` won't grow anymore
+    pub scanned: bool,
+}
+
+/// a group member along with its pixel-level verification score, if any
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Member {
+    #[serde(flatten)]
+    pub file: FileInfo,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<f64>,
+    /// hash distance to the suggested keeper of the group, unknown in `Histogram` mode
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dist: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum GroupStatus {
+    New,
+    Changed,
+    Unchanged,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Group {
+    pub files: Vec<Member>,
+    /// compared to the previous analysis with the same parameters, if there was one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<GroupStatus>,
 }
 
-type Hashes = Vec<(FileInfo, ImageHash)>;
+impl Group {
+    /// the file suggested to keep: the largest one, the oldest among equally large ones
+    pub fn keeper(&self) -> Option<&Member> {
+        self.files
+            .iter()
+            .max_by(|a, b| a.file.size.cmp(&b.file.size).then(b.file.date.cmp(&a.file.date)))
+    }
+
+    /// the files besides the keeper, removing them reclaims their space
+    pub fn duplicates(&self) -> impl Iterator<Item = &Member> {
+        let keeper = self.keeper().map(|m| &m.file.path);
+        self.files.iter().filter(move |m| Some(&m.file.path) != keeper)
+    }
+
+    /// the bytes reclaimed by keeping only the keeper
+    pub fn savings(&self) -> u64 {
+        self.duplicates().map(|m| m.file.size).sum()
+    }
+}
 
-pub type Groups = Vec<Vec<FileInfo>>;
+pub type Groups = Vec<Group>;
 
-fn create_groups(hashes: &Hashes, max_dist: u32) -> Groups {
+/// the outcome of an analysis
+#[derive(Debug, Default)]
+pub struct Analysis {
+    pub groups: Groups,
+    /// the groups of the previous analysis with the same parameters
+    /// none of whose files are grouped anymore, see `GroupStatus`
+    pub removed: Vec<BTreeSet<PathBuf>>,
+}
+
+/// measures the hash distance of every member to the suggested keeper
+fn measure_distances(group: &mut Group, features: &HashMap<&PathBuf, &Features>) {
+    let whole_hash = |path: &PathBuf| features.get(path).and_then(|f| f.whole_hash()).cloned();
+    let Some(keeper) = group.keeper().and_then(|m| whole_hash(&m.file.path)) else {
+        return;
+    };
+
+    for member in &mut group.files {
+        member.dist = whole_hash(&member.file.path).map(|hash| hash.dist(&keeper));
+    }
+}
+
+type Edges = Vec<(PathBuf, PathBuf)>;
+
+/// groups similar files; with a previous snapshot only new or changed files
+/// are compared to the others, similarities between the rest are taken from the snapshot
+fn create_groups(hashes: &Hashes, req: &AnalyzeRequest, previous: Option<&Snapshot>) -> (Vec<Vec<FileInfo>>, Edges) {
     let mut ds = disjoint_set::DisjointSet::new();
+    let mut edges = Vec::new();
 
     for (k, _) in hashes {
         ds.insert(k.clone());
     }
 
-    for (k1, h1) in hashes {
-        for (k2, h2) in hashes {
-            if k1.path != k2.path && h1.dist(h2) <= max_dist {
-                ds.union(k1, k2);
+    // links found with other similarity parameters can't be reused
+    let previous = previous.filter(|p| *p.similarity() == req.similarity());
+
+    let fresh: Vec<_> = hashes
+        .iter()
+        .map(|(k, _)| previous.is_none_or(|p| p.is_fresh(k)))
+        .collect();
+
+    if let Some(previous) = previous {
+        let index: HashMap<_, _> = hashes
+            .iter()
+            .enumerate()
+            .map(|(i, (k, _))| (&k.path, i))
+            .collect();
+
+        for (p1, p2) in previous.edges() {
+            if let (Some(&i), Some(&j)) = (index.get(p1), index.get(p2)) {
+                if !fresh[i] && !fresh[j] {
+                    edges.push((i, j));
+                }
+            }
+        }
+    }
+
+    // only new or changed files are compared, each pair of them once
+    for (i, (k1, h1)) in hashes.iter().enumerate().filter(|&(i, _)| fresh[i]) {
+        for (j, (k2, h2)) in hashes.iter().enumerate() {
+            if j == i || (fresh[j] && j < i) {
+                continue;
+            }
+            // only link candidates to the reference collection when there is one
+            let linkable = req.reference.is_none() || k1.reference != k2.reference;
+            if linkable && h1.is_similar(h2, req) {
+                edges.push((i.min(j), i.max(j)));
             }
         }
     }
 
-    ds
+    for &(i, j) in &edges {
+        ds.union(&hashes[i].0, &hashes[j].0);
+    }
+
+    let groups = ds
         .into_vec()
         .into_iter()
         .filter(|v| v.len() > 1)
-        .collect()
+        .collect();
+
+    let edges = edges
+        .into_iter()
+        .map(|(i, j)| (hashes[i].0.path.clone(), hashes[j].0.path.clone()))
+        .collect();
+
+    (groups, edges)
 }
 
-#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, serde::Deserialize)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
 pub enum HashType {
     AHash,
     PHash,
     DHash,
 }
 
-#[derive(Debug, serde::Deserialize)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub enum Mode {
+    /// a single hash of the whole image
+    #[default]
+    Global,
+    /// hashes of overlapping regions, tolerates crops and letterboxing
+    Regions,
+    /// color distribution only, ignores the image structure
+    Histogram,
+}
+
+fn default_shared_regions() -> usize {
+    2
+}
+
+const DEFAULT_COLOR_DIST: f32 = 0.2;
+
+/// hashes outside this range are either meaningless or too costly to compare
+const HASH_SIZES: std::ops::RangeInclusive<u32> = 2..=64;
+
+/// hashers resize images to the square of the hash size
+fn validate_hash_size(hash_size: u32) -> Result<(), Error> {
+    if !HASH_SIZES.contains(&hash_size) {
+        let msg = format!("the hash size must be between {} and {}", HASH_SIZES.start(), HASH_SIZES.end());
+        return Err(Error::BadRequest(msg));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct AnalyzeRequest {
     pub dist: u32,
-    pub path: PathBuf,
+    pub paths: Vec<PathBuf>,
+    /// an archive to compare `paths` against, when set only groups linking
+    /// files from `paths` to files from the archive are reported
+    #[serde(default)]
+    pub reference: Option<PathBuf>,
     pub hash_type: HashType,
     pub hash_size: u32,
+    #[serde(flatten)]
+    pub scan: ScanOptions,
+    /// minimal structural similarity (0..1) required to keep images
+    /// in the same group, disables the verification pass when missing
+    #[serde(default)]
+    pub verify: Option<f64>,
+    #[serde(default)]
+    pub mode: Mode,
+    /// number of matching regions required to group two images in `Regions` mode
+    #[serde(default = "default_shared_regions")]
+    pub shared_regions: usize,
+    /// max color histogram distance (0..1), when set in `Global` or `Regions` mode
+    /// images must agree both in structure and in colors to be grouped
+    #[serde(default)]
+    pub color_dist: Option<f32>,
+}
+
+/// the parameters deciding whether two hashed images are similar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Similarity {
+    dist: u32,
+    shared_regions: usize,
+    color_dist: Option<f32>,
+}
+
+impl AnalyzeRequest {
+    /// identifies the analyses of the same files hashed the same way,
+    /// whose snapshots can be reused
+    fn snapshot_key(&self) -> Result<String> {
+        let key = (&self.paths, &self.reference, self.hash_type, self.hash_size, &self.scan, self.mode);
+        Ok(serde_json::to_string(&key)?)
+    }
+
+    pub fn similarity(&self) -> Similarity {
+        Similarity { dist: self.dist, shared_regions: self.shared_regions, color_dist: self.color_dist }
+    }
+
+    /// rejects parameters outside of their meaningful range
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_hash_size(self.hash_size)?;
+        if self.verify.is_some_and(|threshold| !(0.0..=1.0).contains(&threshold)) {
+            return Err(Error::BadRequest("the verification threshold must be between 0 and 1".to_owned()));
+        }
+        Ok(())
+    }
+
+    fn uses_color(&self) -> bool {
+        self.mode == Mode::Histogram || self.color_dist.is_some()
+    }
+
+    fn color_threshold(&self) -> f32 {
+        self.color_dist.unwrap_or(DEFAULT_COLOR_DIST)
+    }
+}
+
+/// looks up images similar to the given one among already hashed files
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchRequest {
+    pub dist: u32,
+    /// the example image, when missing it is expected in the request body
+    #[serde(default)]
+    pub path: Option<PathBuf>,
+    pub hash_type: HashType,
+    pub hash_size: u32,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl SearchRequest {
+    pub fn validate(&self) -> Result<(), Error> {
+        validate_hash_size(self.hash_size)
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SearchMatch {
+    #[serde(flatten)]
+    pub file: FileInfo,
+    pub dist: u32,
 }
 
 type CacheKey = (HashType, u32, PathBuf);
 
 pub struct Analyzer {
-    cache: Cache<CacheKey, ImageHash>,
+    cache: Cache<CacheKey, Stamped<ImageHash>>,
+    region_cache: Cache<CacheKey, Stamped<Vec<ImageHash>>>,
+    histogram_cache: Cache<PathBuf, Stamped<Histogram>>,
+    /// the hashes of `cache` by their kind and size, for similarity lookups
+    indexes: Mutex<HashMap<(HashType, u32), HashIndex>>,
+    /// kinds and sizes of the cached hashes, to evict a file without a full pass over the caches
+    kinds: Mutex<HashSet<(HashType, u32)>>,
+    snapshots: Mutex<Snapshots>,
 }
 
 impl Analyzer {
     pub fn new() -> Self {
-        Self { cache: Cache::new() }
+        Self {
+            cache: Cache::new(),
+            region_cache: Cache::new(),
+            histogram_cache: Cache::new(),
+            indexes: Mutex::new(HashMap::new()),
+            kinds: Mutex::new(HashSet::new()),
+            snapshots: Mutex::new(Snapshots::default()),
+        }
     }
 
-    fn make_hasher(req: &AnalyzeRequest) -> Hasher {
-        let (hash_alg, dct) = match req.hash_type {
+    fn make_hasher(hash_type: HashType, hash_size: u32) -> Hasher {
+        let (hash_alg, dct) = match hash_type {
             HashType::AHash => (HashAlg::Mean, false),
             HashType::PHash => (HashAlg::Mean, true),
             HashType::DHash => (HashAlg::Gradient, false),
         };
 
         let mut config = HasherConfig::new()
-            .hash_size(req.hash_size, req.hash_size)
+            .hash_size(hash_size, hash_size)
             .hash_alg(hash_alg);
 
         if dct {
@@ -134,62 +508,392 @@ impl Analyzer {
         (req.hash_type, req.hash_size, file_path)
     }
 
-    fn compute_hash(&self, req: &AnalyzeRequest, hasher: &Hasher, file: FileInfo) -> Option<(FileInfo, ImageHash)> {
+    /// caches the hash of the whole image and indexes it
+    fn set_hash(&self, key: CacheKey, stamp: Stamp, hash: &ImageHash) -> Result<()> {
+        let (hash_type, hash_size, path) = key.clone();
+        self.cache.set(key, (stamp, hash.clone()))?;
+        self.index_hash(hash_type, hash_size, path, hash);
+        Ok(())
+    }
+
+    fn index_hash(&self, hash_type: HashType, hash_size: u32, path: PathBuf, hash: &ImageHash) {
+        self.indexes
+            .lock()
+            .unwrap()
+            .entry((hash_type, hash_size))
+            .or_default()
+            .insert(path, hash.clone());
+    }
+
+    /// looks the features up in the caches; missing ones are claimed, so that concurrent
+    /// analyses of the same file wait for them instead of computing them again
+    fn cached_features(&self, req: &AnalyzeRequest, file: &FileInfo) -> Result<(Features, Claims), Vec<Wait>> {
         let key = Self::cache_key(req, file.path.clone());
-        if let Ok(Some(hash)) = self.cache.get(key) {
-            Some((file, hash))
+        let stamp = file.stamp();
+        let mut features = Features::default();
+        let mut claims = Claims::default();
+        let mut waits = Vec::new();
+
+        match req.mode {
+            Mode::Global => (features.hash, claims.hash) = claim_feature(&self.cache, key, stamp, &mut waits),
+            Mode::Regions => (features.regions, claims.regions) = claim_feature(&self.region_cache, key, stamp, &mut waits),
+            Mode::Histogram => {}
+        }
+
+        if req.uses_color() {
+            (features.histogram, claims.histogram) = claim_feature(&self.histogram_cache, file.path.clone(), stamp, &mut waits);
+        }
+
+        // the claims taken so far are released, others could be waiting for them
+        if waits.is_empty() {
+            Ok((features, claims))
         } else {
-            let path = file.path.to_str();
-            tracing::info!(path, "analyzing");
-            match image::open(&file.path) {
-                Ok(image) => {
-                    let hash = hasher.hash_image(&image);
-                    Some((file, hash))
-                }
-                Err(err) => {
-                    tracing::error!(path, "unable to open the image: {:?}", err);
-                    None
-                }
-            }
+            Err(waits)
         }
     }
 
-    fn compute_hashes(&self, req: &AnalyzeRequest, tx: watch::Sender<usize>) -> Result<Hashes> {
-        let files = list_dir(&req.path)?;
-        let hasher = Self::make_hasher(req);
-        let total = files.len();
-        let iter = files.into_par_iter();
-        let counter = AtomicUsize::new(0);
+    /// sets the claimed features that were computed, the other claims are released
+    fn publish_features(&self, req: &AnalyzeRequest, file: &FileInfo, claims: Claims, computed: &Features) {
+        let stamp = file.stamp();
 
-        let result = iter.filter_map(|file| {
-            let prev = counter.fetch_add(1, Ordering::Relaxed);
-            let progress = prev * 100 / total;
-            if tx.send(progress).is_err() {
-                tracing::error!(path = file.path.to_str(), "unable to report progress");
+        if let (Some(guard), Some(hash)) = (claims.hash, &computed.hash) {
+            guard.set((stamp, hash.clone()));
+            self.index_hash(req.hash_type, req.hash_size, file.path.clone(), hash);
+        }
+        if let (Some(guard), Some(regions)) = (claims.regions, &computed.regions) {
+            guard.set((stamp, regions.clone()));
+        }
+        if let (Some(guard), Some(histogram)) = (claims.histogram, &computed.histogram) {
+            guard.set((stamp, histogram.clone()));
+        }
+    }
+
+    fn compute_hash(&self, req: &AnalyzeRequest, hasher: &Hasher, file: FileInfo) -> Hashed {
+        let (cached, claims) = match self.cached_features(req, &file) {
+            Ok(found) => found,
+            Err(waits) => return Hashed::Deferred(file, waits),
+        };
+        if cached.is_complete(req) {
+            return Hashed::Done(Some((file, cached)));
+        }
+
+        let path = file.path.to_str();
+        tracing::info!(path, "analyzing");
+        let image = match image::open(&file.path) {
+            Ok(image) => image,
+            Err(err) => {
+                tracing::error!(path, "unable to open the image: {:?}", err);
+                return Hashed::Done(None);
+            }
+        };
+
+        let mut features = cached;
+        features.compute(req, hasher, &image);
+        self.publish_features(req, &file, claims, &features);
+        Hashed::Done(Some((file, features)))
+    }
+
+    fn compute_hashes(&self, req: &AnalyzeRequest, pool: &ThreadPool, tx: watch::Sender<Progress>) -> Result<Hashes> {
+        let mut roots: Vec<_> = scanner::dedup_roots(&req.paths)?
+            .into_iter()
+            .map(|path| Root { path, reference: false })
+            .collect();
+
+        if let Some(reference) = &req.reference {
+            // the analyzed folders nested inside the archive are not traversed twice
+            let path = fs::canonicalize(reference).map_err(|err| Error::io(reference, err))?;
+            roots.push(Root { path, reference: true });
+        }
+
+        let hasher = Self::make_hasher(req.hash_type, req.hash_size);
+        let discovered = AtomicUsize::new(0);
+        let processed = AtomicUsize::new(0);
+
+        let report = |scanned: bool| {
+            tx.send_modify(|progress| {
+                progress.discovered = discovered.load(Ordering::Relaxed);
+                progress.processed = processed.load(Ordering::Relaxed);
+                progress.scanned |= scanned;
+            });
+        };
+
+        let hash = |file| {
+            let hashed = self.compute_hash(req, &hasher, file);
+            if let Hashed::Done(_) = hashed {
+                processed.fetch_add(1, Ordering::Relaxed);
+                report(false);
+            }
+            hashed
+        };
+
+        let (files_tx, files_rx) = mpsc::channel();
+
+        // hashing starts as soon as the first files are found
+        let (links, mut outcomes) = thread::scope(|scope| {
+            let scanner = scope.spawn(|| {
+                let links = scanner::scan(&roots, &req.scan, files_tx, &discovered);
+                report(true);
+                links
+            });
+
+            let outcomes: Vec<_> = pool.install(|| files_rx.into_iter().par_bridge().map(hash).collect());
+            (scanner.join().unwrap(), outcomes)
+        });
+
+        let mut result = Vec::new();
+        loop {
+            let mut deferred = Vec::new();
+            for outcome in outcomes {
+                match outcome {
+                    Hashed::Done(hashed) => result.extend(hashed),
+                    Hashed::Deferred(file, waits) => deferred.push((file, waits)),
+                }
+            }
+            if deferred.is_empty() {
+                break;
             }
 
-            self.compute_hash(req, &hasher, file)
-        }).collect();
+            // waiting here rather than on the pool keeps its threads busy with other files
+            let files: Vec<_> = deferred
+                .into_iter()
+                .map(|(file, waits)| {
+                    waits.into_iter().for_each(|wait| wait());
+                    file
+                })
+                .collect();
+            outcomes = pool.install(|| files.into_par_iter().map(hash).collect());
+        }
 
-        let progress = counter.into_inner() * 100 / total;
-        tx.send(progress)?;
+        let mut links = links?;
+        let result = result
+            .into_iter()
+            .map(|(mut file, features)| {
+                scanner::attach_links(&mut file, &mut links);
+                (file, features)
+            })
+            .collect();
 
+        report(true);
         Ok(result)
     }
 
-    fn update_cache(&self, req: &AnalyzeRequest, hashes: Hashes) -> Result<()> {
-        for (file, hash) in hashes {
-            let key = Self::cache_key(req, file.path);
-            self.cache.set(key, hash)?;
+    /// runs on the calling thread, the parallel parts are run on `pool`
+    pub fn analyze(&self, req: &AnalyzeRequest, pool: &ThreadPool, tx: watch::Sender<Progress>) -> Result<Analysis> {
+        self.kinds.lock().unwrap().insert((req.hash_type, req.hash_size));
+        let key = req.snapshot_key()?;
+        let previous = self.snapshots.lock().unwrap().get(&key);
+
+        let hashes = self.compute_hashes(req, pool, tx)?;
+        let (groups, edges) = create_groups(&hashes, req, previous.as_deref());
+
+        let mut result = match req.verify {
+            Some(threshold) => pool.install(|| verifier::verify_groups(groups, threshold)),
+            None => groups
+                .into_iter()
+                .map(|group| group.into_iter().map(|file| Member { file, score: None, dist: None }).collect())
+                .collect(),
+        };
+
+        if req.reference.is_some() {
+            // verification could leave groups made of candidates only
+            result.retain(|group| {
+                group.iter().any(|m| m.file.reference) && group.iter().any(|m| !m.file.reference)
+            });
+        }
+
+        let features: HashMap<_, _> = hashes.iter().map(|(file, features)| (&file.path, features)).collect();
+        let result: Groups = result
+            .into_iter()
+            .map(|files| {
+                let mut group = Group { files, status: None };
+                group.status = previous.as_ref().map(|p| p.status(&group));
+                measure_distances(&mut group, &features);
+                group
+            })
+            .collect();
+
+        let removed = previous.map(|p| p.removed(&result)).unwrap_or_default();
+        let snapshot = Snapshot::new(hashes.iter().map(|(file, _)| file), req.similarity(), edges, &result);
+        self.snapshots.lock().unwrap().insert(key, snapshot);
+
+        Ok(Analysis { groups: result, removed })
+    }
+
+    /// looks up cached hashes within `max_dist` of the given one, closest first
+    fn find_similar(&self, hash: &ImageHash, hash_type: HashType, hash_size: u32, max_dist: u32, exclude: Option<&Path>) -> Vec<(PathBuf, u32)> {
+        let indexes = self.indexes.lock().unwrap();
+        let Some(index) = indexes.get(&(hash_type, hash_size)) else {
+            return Vec::new();
+        };
+
+        let mut found = index.find(hash, max_dist);
+        found.retain(|(path, _)| Some(path.as_path()) != exclude);
+        found.sort_by_key(|(_, dist)| *dist);
+        found
+    }
+
+    fn to_matches(found: Vec<(PathBuf, u32)>) -> Vec<SearchMatch> {
+        found
+            .into_iter()
+            .filter_map(|(path, dist)| {
+                // the file could have been removed since it was hashed
+                let file = FileInfo::from_path(&path).ok()?;
+                Some(SearchMatch { file, dist })
+            })
+            .collect()
+    }
+
+    /// finds images similar to the example among the hashes cached by previous analyses,
+    /// the library is not rescanned so only already analyzed folders are searched
+    pub fn search(&self, req: &SearchRequest, data: &[u8]) -> Result<Vec<SearchMatch>> {
+        // indexed paths are canonical, the example itself is excluded by its canonical path
+        let path = match &req.path {
+            Some(path) => Some(fs::canonicalize(path).map_err(|err| Error::io(path, err))?),
+            None => None,
+        };
+        let image = match &path {
+            Some(path) => image::open(path).map_err(|err| Error::UnreadableImage(path.clone(), err))?,
+            None => image::load_from_memory(data)
+                .map_err(|err| Error::BadRequest(format!("unable to read the image: {}", err)))?,
+        };
+
+        let hash = Self::make_hasher(req.hash_type, req.hash_size).hash_image(&image);
+        let mut found = self.find_similar(&hash, req.hash_type, req.hash_size, req.dist, path.as_deref());
+        if let Some(limit) = req.limit {
+            found.truncate(limit);
         }
 
+        Ok(Self::to_matches(found))
+    }
+
+    /// hashes a single new or modified file and caches its hash,
+    /// returns None for files whose hash is cached and up to date
+    pub fn hash_file(&self, path: &Path, hash_type: HashType, hash_size: u32) -> Result<Option<(FileInfo, ImageHash)>> {
+        self.kinds.lock().unwrap().insert((hash_type, hash_size));
+        let file = FileInfo::from_path(path)?;
+        let key = (hash_type, hash_size, file.path.clone());
+        if unless_stale(self.cache.get(key.clone()), file.stamp()).is_some() {
+            return Ok(None);
+        }
+
+        tracing::info!(path = path.to_str(), "indexing");
+        let image = image::open(path)?;
+        let hash = Self::make_hasher(hash_type, hash_size).hash_image(&image);
+        self.set_hash(key, file.stamp(), &hash)?;
+        Ok(Some((file, hash)))
+    }
+
+    /// hashes a single new or modified file and returns the already hashed files similar to it,
+    /// nothing is returned for files whose hash is cached and up to date
+    pub fn index_file(&self, path: &Path, hash_type: HashType, hash_size: u32, max_dist: u32) -> Result<Vec<SearchMatch>> {
+        let Some((file, hash)) = self.hash_file(path, hash_type, hash_size)? else {
+            return Ok(Vec::new());
+        };
+
+        let found = self.find_similar(&hash, hash_type, hash_size, max_dist, Some(&file.path));
+        Ok(Self::to_matches(found))
+    }
+
+    /// forgets everything cached about a removed file
+    pub fn evict(&self, path: &Path) -> Result<()> {
+        let keys: Vec<CacheKey> = self
+            .kinds
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|&(hash_type, hash_size)| (hash_type, hash_size, path.to_owned()))
+            .collect();
+
+        self.cache.remove(keys.clone())?;
+        self.region_cache.remove(keys)?;
+        self.histogram_cache.remove(vec![path.to_owned()])?;
+        for index in self.indexes.lock().unwrap().values_mut() {
+            index.remove(path);
+        }
         Ok(())
     }
+}
 
-    pub fn analyze(&self, req: &AnalyzeRequest, tx: watch::Sender<usize>) -> Result<Groups> {
-        let hashes = self.compute_hashes(req, tx)?;
-        let result = create_groups(&hashes, req.dist);
-        self.update_cache(req, hashes)?;
-        Ok(result)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(dist: u32) -> AnalyzeRequest {
+        let json = serde_json::json!({ "dist": dist, "paths": ["/photos"], "hashType": "DHash", "hashSize": 8 });
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn hashed(name: &str, bits: u64, modified: u64) -> (FileInfo, Features) {
+        let file = FileInfo {
+            path: PathBuf::from("/photos").join(name),
+            size: 1,
+            date: 0,
+            modified,
+            reference: false,
+            root: None,
+            links: Vec::new(),
+        };
+        let hash = ImageHash::from_bytes(&bits.to_be_bytes()).unwrap();
+        (file, Features { hash: Some(hash), ..Default::default() })
+    }
+
+    fn paths(groups: &[Vec<FileInfo>]) -> Vec<Vec<String>> {
+        let mut paths: Vec<Vec<String>> = groups
+            .iter()
+            .map(|group| {
+                let mut names: Vec<_> = group.iter().map(|f| f.path.display().to_string()).collect();
+                names.sort();
+                names
+            })
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn rejects_unsupported_hash_sizes() {
+        let search = |size: u32| {
+            let json = serde_json::json!({ "dist": 1, "hashType": "DHash", "hashSize": size });
+            serde_json::from_value::<SearchRequest>(json).unwrap()
+        };
+        assert!(search(8).validate().is_ok());
+        assert!(matches!(search(100_000).validate(), Err(Error::BadRequest(_))));
+        assert!(matches!(search(1).validate(), Err(Error::BadRequest(_))));
+    }
+
+    #[test]
+    fn groups_similar_hashes() {
+        let hashes = vec![hashed("a", 0, 0), hashed("b", 1, 0), hashed("c", u64::MAX, 0)];
+        let (groups, edges) = create_groups(&hashes, &request(1), None);
+        assert_eq!(paths(&groups), [["/photos/a", "/photos/b"]]);
+        assert_eq!(edges.len(), 1);
+    }
+
+    #[test]
+    fn compares_only_fresh_files_to_the_snapshot() {
+        let req = request(1);
+        let hashes = vec![hashed("a", 0, 0), hashed("b", 1, 0)];
+        let (groups, edges) = create_groups(&hashes, &req, None);
+        let groups: Vec<_> = groups
+            .into_iter()
+            .map(|files| Group { files: files.into_iter().map(|file| Member { file, score: None, dist: None }).collect(), status: None })
+            .collect();
+        let snapshot = Snapshot::new(hashes.iter().map(|(f, _)| f), req.similarity(), edges, &groups);
+
+        // `b` changed and no longer looks like `a`, `c` is new and looks like `a`
+        let hashes = vec![hashed("a", 0, 0), hashed("b", u64::MAX, 1), hashed("c", 0b10, 0)];
+        let (groups, _) = create_groups(&hashes, &req, Some(&snapshot));
+        assert_eq!(paths(&groups), [["/photos/a", "/photos/c"]]);
+
+        // edges found with another distance are not reused
+        let hashes = vec![hashed("a", 0, 0), hashed("b", 1, 0)];
+        let (groups, _) = create_groups(&hashes, &request(0), Some(&snapshot));
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn snapshot_key_ignores_similarity_parameters() {
+        let (a, b) = (request(1), request(5));
+        assert_eq!(a.snapshot_key().unwrap(), b.snapshot_key().unwrap());
     }
 }
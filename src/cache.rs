@@ -8,10 +8,21 @@ use std::{
 use eyre::Result;
 use tokio::sync::oneshot;
 
-#[derive(Debug)]
+type Validator<V> = Box<dyn FnOnce(&V) -> bool + Send>;
+
+enum ClaimReply<V> {
+    Cached(V),
+    Claimed,
+    /// receives the value once set, or None when the claim passes to the receiver
+    Pending(oneshot::Receiver<Option<V>>),
+}
+
 enum CacheCommand<K, V> {
     Get(K, oneshot::Sender<Option<V>>),
     Set(K, V),
+    Claim(K, Validator<V>, oneshot::Sender<ClaimReply<V>>),
+    Release(K),
+    Remove(Vec<K>),
 }
 
 fn task_cache<K, V>(commands: mpsc::Receiver<CacheCommand<K, V>>)
@@ -20,6 +31,8 @@ where
     V: Clone,
 {
     let mut cache: HashMap<K, V> = HashMap::new();
+    // claimed keys along with the callers waiting for their values
+    let mut pending: HashMap<K, Vec<oneshot::Sender<Option<V>>>> = HashMap::new();
 
     for command in commands {
         match command {
@@ -29,8 +42,139 @@ where
                 }
             }
             CacheCommand::Set(key, val) => {
+                for waiter in pending.remove(&key).unwrap_or_default() {
+                    let _ = waiter.send(Some(val.clone()));
+                }
                 cache.insert(key, val);
             }
+            CacheCommand::Claim(key, valid, tx) => {
+                if let Some(waiters) = pending.get_mut(&key) {
+                    let (waiter, rx) = oneshot::channel();
+                    if tx.send(ClaimReply::Pending(rx)).is_ok() {
+                        waiters.push(waiter);
+                    }
+                    continue;
+                }
+                match cache.get(&key) {
+                    Some(val) if valid(val) => {
+                        if tx.send(ClaimReply::Cached(val.clone())).is_err() {
+                            tracing::error!("unable to send cached data for key {:?}", key);
+                        }
+                    }
+                    _ => {
+                        // nobody to compute the value if the caller is gone
+                        if tx.send(ClaimReply::Claimed).is_ok() {
+                            pending.insert(key, Vec::new());
+                        }
+                    }
+                }
+            }
+            CacheCommand::Release(key) => {
+                let mut waiters = pending.remove(&key).unwrap_or_default();
+                // the claim passes to the next waiter, which computes the value on its own
+                while !waiters.is_empty() {
+                    if waiters.remove(0).send(None).is_ok() {
+                        pending.insert(key, waiters);
+                        break;
+                    }
+                }
+            }
+            CacheCommand::Remove(keys) => {
+                for key in keys {
+                    cache.remove(&key);
+                }
+            }
+        }
+    }
+}
+
+/// the outcome of `Cache::claim`
+pub enum Claim<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    Cached(V),
+    /// the caller has to compute the value
+    Claimed(ClaimGuard<K, V>),
+    /// another caller is computing the value
+    Pending(Waiter<K, V>),
+}
+
+/// a claimed key, released when dropped unless its value was set
+pub struct ClaimGuard<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    key: Option<K>,
+    commands: mpsc::Sender<CacheCommand<K, V>>,
+}
+
+impl<K, V> ClaimGuard<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    pub fn set(mut self, val: V) {
+        if let Some(key) = self.key.take() {
+            let _ = self.commands.send(CacheCommand::Set(key, val));
+        }
+    }
+}
+
+impl<K, V> Drop for ClaimGuard<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        // also when the claimer panics, so that waiters don't wait forever
+        if let Some(key) = self.key.take() {
+            let _ = self.commands.send(CacheCommand::Release(key));
+        }
+    }
+}
+
+/// waits for the value of a key claimed by another caller
+pub struct Waiter<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    key: K,
+    rx: Option<oneshot::Receiver<Option<V>>>,
+    commands: mpsc::Sender<CacheCommand<K, V>>,
+}
+
+impl<K, V> Waiter<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// blocks until the value is set, or until the claim is released and passes to this caller
+    pub fn wait(mut self) -> Result<Claim<K, V>> {
+        let rx = self.rx.take().expect("waited once");
+        Ok(match rx.blocking_recv()? {
+            Some(val) => Claim::Cached(val),
+            None => Claim::Claimed(ClaimGuard { key: Some(self.key.clone()), commands: self.commands.clone() }),
+        })
+    }
+}
+
+impl<K, V> Drop for Waiter<K, V>
+where
+    K: Eq + Hash + Debug + Clone + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    fn drop(&mut self) {
+        let Some(mut rx) = self.rx.take() else {
+            return;
+        };
+        // a claim passed to a waiter that gave up is passed on
+        rx.close();
+        if let Ok(None) = rx.try_recv() {
+            let _ = self.commands.send(CacheCommand::Release(self.key.clone()));
         }
     }
 }
@@ -41,7 +185,7 @@ pub struct Cache<K, V> {
 
 impl<K, V> Cache<K, V>
 where
-    K: Eq + Hash + Debug + Send + 'static,
+    K: Eq + Hash + Debug + Clone + Send + 'static,
     V: Clone + Send + 'static,
 {
     pub fn new() -> Self {
@@ -60,4 +204,76 @@ where
         self.commands.send(CacheCommand::Set(key, val)).unwrap();
         Ok(())
     }
+
+    /// returns the cached value if `valid` accepts it; otherwise claims the key for the
+    /// caller to compute the value, unless another caller has claimed it already
+    pub fn claim<F>(&self, key: K, valid: F) -> Result<Claim<K, V>>
+    where
+        F: FnOnce(&V) -> bool + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.commands.send(CacheCommand::Claim(key.clone(), Box::new(valid), tx)).unwrap();
+        let commands = self.commands.clone();
+        Ok(match rx.blocking_recv()? {
+            ClaimReply::Cached(val) => Claim::Cached(val),
+            ClaimReply::Claimed => Claim::Claimed(ClaimGuard { key: Some(key), commands }),
+            ClaimReply::Pending(rx) => Claim::Pending(Waiter { key, rx: Some(rx), commands }),
+        })
+    }
+
+    /// evicts the given keys, missing ones are ignored
+    pub fn remove(&self, keys: Vec<K>) -> Result<()> {
+        self.commands.send(CacheCommand::Remove(keys)).unwrap();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claim(cache: &Cache<u32, u32>) -> Claim<u32, u32> {
+        cache.claim(1, |_| true).unwrap()
+    }
+
+    #[test]
+    fn waiters_get_the_value_set_by_the_claimer() {
+        let cache = Cache::new();
+        let Claim::Claimed(guard) = claim(&cache) else { panic!("not claimed") };
+        let Claim::Pending(waiter) = claim(&cache) else { panic!("not pending") };
+
+        guard.set(10);
+        assert!(matches!(waiter.wait().unwrap(), Claim::Cached(10)));
+        assert!(matches!(claim(&cache), Claim::Cached(10)));
+    }
+
+    #[test]
+    fn dropped_claims_pass_to_the_next_waiter() {
+        let cache: Cache<u32, u32> = Cache::new();
+        let Claim::Claimed(guard) = claim(&cache) else { panic!("not claimed") };
+        let Claim::Pending(waiter) = claim(&cache) else { panic!("not pending") };
+
+        // e.g. the claimer panicked
+        let claimer = thread::spawn(move || {
+            let _guard = guard;
+            panic!("unable to decode");
+        });
+        assert!(claimer.join().is_err());
+
+        let Claim::Claimed(guard) = waiter.wait().unwrap() else { panic!("not claimed") };
+        drop(guard);
+        assert!(matches!(claim(&cache), Claim::Claimed(_)));
+    }
+
+    #[test]
+    fn claims_of_dropped_waiters_are_passed_on() {
+        let cache: Cache<u32, u32> = Cache::new();
+        let Claim::Claimed(guard) = claim(&cache) else { panic!("not claimed") };
+        let Claim::Pending(first) = claim(&cache) else { panic!("not pending") };
+        let Claim::Pending(second) = claim(&cache) else { panic!("not pending") };
+
+        drop(first);
+        drop(guard);
+        assert!(matches!(second.wait().unwrap(), Claim::Claimed(_)));
+    }
 }
@@ -0,0 +1,82 @@
+use eyre::{eyre, Report, Result};
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::error::Error;
+use crate::export::{self, Format};
+use crate::plan;
+use crate::remover::Remover;
+use crate::store::TaskStore;
+use crate::{REMOVED_ROOT, RESULTS_ROOT};
+
+const USAGE: &str = "\
+usage: image-analyzer [COMMAND]
+
+Runs the server when no command is given.
+
+commands:
+    export <task-id> [--format json|csv|html] [--output <file>]
+        renders a stored analysis result, to stdout by default
+    plan <file> [--apply]
+        checks a deletion plan (CSV or JSON) against the files,
+        trashes the files it marks with --apply";
+
+fn usage() -> Report {
+    eyre!(USAGE)
+}
+
+fn export(args: &[String]) -> Result<()> {
+    let mut task_id = None;
+    let mut format = Format::default();
+    let mut output = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => format = args.next().ok_or_else(usage)?.parse()?,
+            "--output" => output = Some(PathBuf::from(args.next().ok_or_else(usage)?)),
+            id => task_id = Some(id.parse::<Uuid>()?),
+        }
+    }
+
+    let task_id = task_id.ok_or_else(usage)?;
+    let store = TaskStore::new(RESULTS_ROOT);
+    let analysis = store.load_result(&task_id).ok_or(Error::TaskNotFound(task_id))??;
+    let content = export::export(&analysis.groups, format, &format!("Analysis {}", task_id))?;
+
+    match output {
+        Some(path) => fs::write(path, content)?,
+        None => io::stdout().write_all(&content)?,
+    }
+
+    Ok(())
+}
+
+fn apply_plan(args: &[String]) -> Result<()> {
+    let mut path = None;
+    let mut apply = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--apply" => apply = true,
+            file => path = Some(PathBuf::from(file)),
+        }
+    }
+
+    let data = fs::read(path.ok_or_else(usage)?)?;
+    let report = plan::run(&data, &Remover::new(REMOVED_ROOT), !apply)?;
+    serde_json::to_writer_pretty(io::stdout(), &report)?;
+    println!();
+    Ok(())
+}
+
+/// runs a command given on the command line instead of the server
+pub fn run(args: &[String]) -> Result<()> {
+    match args.first().map(String::as_str) {
+        Some("export") => export(&args[1..]),
+        Some("plan") => apply_plan(&args[1..]),
+        _ => Err(usage()),
+    }
+}
@@ -0,0 +1,133 @@
+use eyre::Report;
+use axum::http::StatusCode;
+use serde_json::{json, Map, Value};
+use std::{io, path::{Path, PathBuf}};
+use uuid::Uuid;
+
+/// errors the client can act upon; returned wrapped into `eyre::Report`
+/// and recovered by downcasting when building the response
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("folder not found")]
+    FolderNotFound(PathBuf),
+    #[error("file not found")]
+    FileNotFound(PathBuf),
+    #[error("permission denied")]
+    PermissionDenied(PathBuf),
+    #[error("unable to read the image")]
+    UnreadableImage(PathBuf, #[source] image::ImageError),
+    #[error("file system error")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("task not found")]
+    TaskNotFound(Uuid),
+    #[error("analysis failed: {1}")]
+    TaskFailed(Uuid, String),
+    /// stopped by a server shutdown and not resumed
+    #[error("the task was interrupted")]
+    TaskInterrupted(Uuid),
+    /// the task panicked or was cancelled
+    #[error("analysis aborted: {0}")]
+    TaskAborted(String),
+    #[error("removed file not found")]
+    TrashEntryNotFound(String),
+    #[error("removed file entry is corrupt")]
+    CorruptTrashEntry(String, #[source] Report),
+    /// the original location of a removed file is taken
+    #[error("a file already exists at the original location")]
+    RestoreConflict(String, PathBuf),
+}
+
+impl Error {
+    /// classifies a file system error by its kind
+    pub fn io(path: &Path, err: io::Error) -> Self {
+        let path = path.to_owned();
+        match err.kind() {
+            io::ErrorKind::NotFound => Self::FileNotFound(path),
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied(path),
+            _ => Self::Io(path, err),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::FolderNotFound(_)
+            | Self::FileNotFound(_)
+            | Self::TaskNotFound(_)
+            | Self::TrashEntryNotFound(_) => StatusCode::NOT_FOUND,
+            Self::PermissionDenied(_) => StatusCode::FORBIDDEN,
+            Self::UnreadableImage(..) => StatusCode::UNPROCESSABLE_ENTITY,
+            Self::TaskFailed(..) | Self::TaskInterrupted(_) | Self::RestoreConflict(..) => StatusCode::CONFLICT,
+            Self::Io(..) | Self::TaskAborted(_) | Self::CorruptTrashEntry(..) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// machine readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::BadRequest(_) => "bad_request",
+            Self::FolderNotFound(_) => "folder_not_found",
+            Self::FileNotFound(_) => "file_not_found",
+            Self::PermissionDenied(_) => "permission_denied",
+            Self::UnreadableImage(..) => "unreadable_image",
+            Self::Io(..) => "io_error",
+            Self::TaskNotFound(_) => "task_not_found",
+            Self::TaskFailed(..) => "task_failed",
+            Self::TaskInterrupted(_) => "task_interrupted",
+            Self::TaskAborted(_) => "task_aborted",
+            Self::TrashEntryNotFound(_) => "trash_entry_not_found",
+            Self::CorruptTrashEntry(..) => "corrupt_trash_entry",
+            Self::RestoreConflict(..) => "restore_conflict",
+        }
+    }
+
+    fn context(&self) -> Map<String, Value> {
+        let context = match self {
+            Self::BadRequest(_) | Self::TaskAborted(_) => json!({}),
+            Self::FolderNotFound(path)
+            | Self::FileNotFound(path)
+            | Self::PermissionDenied(path)
+            | Self::UnreadableImage(path, _)
+            | Self::Io(path, _) => json!({ "path": path }),
+            Self::TaskNotFound(id) | Self::TaskFailed(id, _) | Self::TaskInterrupted(id) => json!({ "taskId": id }),
+            Self::TrashEntryNotFound(id) | Self::CorruptTrashEntry(id, _) => json!({ "id": id }),
+            Self::RestoreConflict(id, path) => json!({ "id": id, "path": path }),
+        };
+
+        match context {
+            Value::Object(map) => map,
+            _ => Map::new(),
+        }
+    }
+}
+
+/// the response body of a failed request
+#[derive(Debug, serde::Serialize)]
+pub struct ErrorBody {
+    code: &'static str,
+    message: String,
+    context: Map<String, Value>,
+}
+
+impl ErrorBody {
+    /// error causes are only exposed by debug builds, they could reveal server internals
+    pub fn new(report: &Report) -> (StatusCode, Self) {
+        let expose = cfg!(debug_assertions);
+
+        let Some(err) = report.downcast_ref::<Error>() else {
+            let message = if expose { format!("{:#}", report) } else { "internal error".to_owned() };
+            let body = Self { code: "internal", message, context: Map::new() };
+            return (StatusCode::INTERNAL_SERVER_ERROR, body);
+        };
+
+        let message = if expose { format!("{:#}", report) } else { err.to_string() };
+        let body = Self { code: err.code(), message, context: err.context() };
+        (err.status(), body)
+    }
+
+    pub fn code(&self) -> &'static str {
+        self.code
+    }
+}
@@ -0,0 +1,259 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::DateTime;
+use eyre::{eyre, Report, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::analyzer::{Group, GroupStatus, Groups, Member};
+use crate::results::Summary;
+use crate::thumbnail::{self, THUMBNAIL_SIZE};
+
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Html => "html",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "html" => Ok(Self::Html),
+            _ => Err(eyre!("unknown export format {:?}, expected json, csv or html", s)),
+        }
+    }
+}
+
+/// a group member as exported, groups are numbered from 1
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Row<'a> {
+    group: usize,
+    path: &'a Path,
+    size: u64,
+    created: String,
+    modified: String,
+    dist: Option<u32>,
+    score: Option<f64>,
+    keeper: bool,
+    /// bytes freed by removing the file, none for the keeper
+    reclaimable: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportGroup<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<GroupStatus>,
+    savings: u64,
+    files: Vec<Row<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Export<'a> {
+    summary: Summary,
+    groups: Vec<ExportGroup<'a>>,
+}
+
+/// formats milliseconds since the epoch as UTC date and time
+pub fn format_time(ms: u64) -> String {
+    DateTime::from_timestamp_millis(ms as i64)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if size < 1024 {
+        return format!("{} B", size);
+    }
+
+    let mut value = size as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+fn rows(index: usize, group: &Group) -> Vec<Row<'_>> {
+    let keeper = group.keeper().map(|m| &m.file.path);
+    group
+        .files
+        .iter()
+        .map(|Member { file, score, dist }| {
+            let is_keeper = Some(&file.path) == keeper;
+            Row {
+                group: index + 1,
+                path: &file.path,
+                size: file.size,
+                created: format_time(file.date),
+                modified: format_time(file.modified),
+                dist: *dist,
+                score: *score,
+                keeper: is_keeper,
+                reclaimable: if is_keeper { 0 } else { file.size },
+            }
+        })
+        .collect()
+}
+
+fn to_json(groups: &Groups) -> Result<Vec<u8>> {
+    let export = Export {
+        summary: Summary::new(groups),
+        groups: groups
+            .iter()
+            .enumerate()
+            .map(|(i, group)| ExportGroup { status: group.status, savings: group.savings(), files: rows(i, group) })
+            .collect(),
+    };
+
+    Ok(serde_json::to_vec_pretty(&export)?)
+}
+
+fn to_csv(groups: &Groups) -> Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for (i, group) in groups.iter().enumerate() {
+        for row in rows(i, group) {
+            writer.serialize(row)?;
+        }
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+fn escape(text: &str) -> String {
+    text
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; color: #222; }
+section { margin-bottom: 2em; }
+.files { display: flex; flex-wrap: wrap; gap: 1em; }
+figure { margin: 0; padding: 0.5em; width: 200px; border: 2px solid #ddd; border-radius: 4px; }
+figure.keeper { border-color: #198754; }
+figure img { display: block; max-width: 100%; margin: 0 auto 0.5em; }
+figcaption { font-size: 0.8em; word-break: break-all; }
+.missing { height: 120px; display: flex; align-items: center; justify-content: center; color: #999; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+td, th { padding: 0.2em 1em; text-align: left; border-bottom: 1px solid #ddd; }
+";
+
+/// a self-contained page, thumbnails are embedded as data URLs
+fn to_html(groups: &Groups, title: &str) -> Result<Vec<u8>> {
+    let paths: Vec<&Path> = groups.iter().flat_map(|g| &g.files).map(|m| m.file.path.as_path()).collect();
+    let thumbnails: HashMap<&Path, String> = paths
+        .par_iter()
+        .filter_map(|path| match thumbnail::thumbnail(path, THUMBNAIL_SIZE) {
+            Ok(data) => Some((*path, STANDARD.encode(data))),
+            Err(err) => {
+                tracing::error!(path = path.to_str(), "unable to render the thumbnail: {:?}", err);
+                None
+            }
+        })
+        .collect();
+
+    let title = escape(title);
+    let mut html = String::new();
+    writeln!(html, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+    writeln!(html, "<title>{}</title>\n<style>\n{}</style>\n</head>\n<body>", title, STYLE)?;
+    writeln!(html, "<h1>{}</h1>\n<p>{} groups, {} images</p>", title, groups.len(), paths.len())?;
+
+    let summary = Summary::new(groups);
+    writeln!(html, "<p>{} can be reclaimed by removing {} duplicates</p>", format_size(summary.duplicate_bytes), summary.duplicates)?;
+    if !summary.folders.is_empty() {
+        writeln!(html, "<table>\n<tr><th>Folder</th><th>Duplicates</th><th>Reclaimable</th></tr>")?;
+        for folder in &summary.folders {
+            let name = escape(&folder.folder.to_string_lossy());
+            writeln!(html, "<tr><td>{}</td><td>{}</td><td>{}</td></tr>", name, folder.duplicates, format_size(folder.bytes))?;
+        }
+        writeln!(html, "</table>")?;
+    }
+
+    for (i, group) in groups.iter().enumerate() {
+        write!(html, "<section>\n<h2>Group {} ({} images, {} reclaimable)</h2>", i + 1, group.files.len(), format_size(group.savings()))?;
+        writeln!(html, "\n<div class=\"files\">")?;
+        for row in rows(i, group) {
+            let class = if row.keeper { " class=\"keeper\"" } else { "" };
+            writeln!(html, "<figure{}>", class)?;
+            match thumbnails.get(row.path) {
+                Some(data) => writeln!(html, "<img src=\"data:image/jpeg;base64,{}\">", data)?,
+                None => writeln!(html, "<div class=\"missing\">no preview</div>")?,
+            }
+            write!(html, "<figcaption>{}<br>{}, {}", escape(&row.path.to_string_lossy()), format_size(row.size), row.modified)?;
+            if let Some(dist) = row.dist {
+                write!(html, ", distance {}", dist)?;
+            }
+            if let Some(score) = row.score {
+                write!(html, ", score {:.2}", score)?;
+            }
+            if row.keeper {
+                write!(html, "<br><strong>suggested to keep</strong>")?;
+            }
+            writeln!(html, "</figcaption>\n</figure>")?;
+        }
+        writeln!(html, "</div>\n</section>")?;
+    }
+
+    writeln!(html, "</body>\n</html>")?;
+    Ok(html.into_bytes())
+}
+
+/// renders analysis results for archiving or sharing
+pub fn export(groups: &Groups, format: Format, title: &str) -> Result<Vec<u8>> {
+    match format {
+        Format::Json => to_json(groups),
+        Format::Csv => to_csv(groups),
+        Format::Html => to_html(groups, title),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_times_in_utc() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00");
+        assert_eq!(format_time(951_782_400_000), "2000-02-29 00:00:00");
+        assert_eq!(format_time(1_700_000_000_999), "2023-11-14 22:13:20");
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+    }
+}
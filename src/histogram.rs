@@ -0,0 +1,92 @@
+use image::{imageops::FilterType, DynamicImage};
+
+const HUE_BINS: usize = 12;
+const SAT_BINS: usize = 3;
+const VAL_BINS: usize = 3;
+
+/// images are downscaled before counting colors, the exact size doesn't matter much
+const SAMPLE_SIZE: u32 = 128;
+
+/// normalized HSV color histogram, bins sum up to 1
+pub type Histogram = Vec<f32>;
+
+fn to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+
+    let sat = if max == 0.0 { 0.0 } else { delta / max };
+    (hue, sat, max)
+}
+
+fn bin(value: f32, range: f32, bins: usize) -> usize {
+    ((value / range * bins as f32) as usize).min(bins - 1)
+}
+
+pub fn compute(image: &DynamicImage) -> Histogram {
+    let sample = image.resize(SAMPLE_SIZE, SAMPLE_SIZE, FilterType::Nearest).to_rgb8();
+    let mut histogram = vec![0.0; HUE_BINS * SAT_BINS * VAL_BINS];
+
+    for pixel in sample.pixels() {
+        let (h, s, v) = to_hsv(pixel[0], pixel[1], pixel[2]);
+        let index = (bin(h, 360.0, HUE_BINS) * SAT_BINS + bin(s, 1.0, SAT_BINS)) * VAL_BINS + bin(v, 1.0, VAL_BINS);
+        histogram[index] += 1.0;
+    }
+
+    let total = (sample.width() * sample.height()).max(1) as f32;
+    for value in histogram.iter_mut() {
+        *value /= total;
+    }
+
+    histogram
+}
+
+/// histogram intersection distance: 0 for identical color distributions,
+/// 1 for distributions having no colors in common
+pub fn distance(a: &Histogram, b: &Histogram) -> f32 {
+    let common: f32 = a.iter().zip(b).map(|(x, y)| x.min(*y)).sum();
+    (1.0 - common).max(0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn solid(r: u8, g: u8, b: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_pixel(32, 32, Rgb([r, g, b])))
+    }
+
+    #[test]
+    fn converts_primaries_to_hsv() {
+        assert_eq!(to_hsv(255, 0, 0), (0.0, 1.0, 1.0));
+        assert_eq!(to_hsv(0, 255, 0), (120.0, 1.0, 1.0));
+        assert_eq!(to_hsv(0, 0, 255), (240.0, 1.0, 1.0));
+        assert_eq!(to_hsv(0, 0, 0), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn bins_sum_up_to_one() {
+        let histogram = compute(&solid(10, 200, 30));
+        assert!((histogram.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert_eq!(histogram.iter().filter(|&&v| v > 0.0).count(), 1);
+    }
+
+    #[test]
+    fn measures_common_colors() {
+        let (red, blue) = (compute(&solid(255, 0, 0)), compute(&solid(0, 0, 255)));
+        assert_eq!(distance(&red, &red), 0.0);
+        assert_eq!(distance(&red, &blue), 1.0);
+    }
+}
@@ -0,0 +1,137 @@
+use image_hasher::ImageHash;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+struct Node {
+    hash: ImageHash,
+    /// files having exactly this hash, emptied nodes are kept to route lookups
+    paths: Vec<PathBuf>,
+    /// child nodes by their distance to this one
+    children: HashMap<u32, usize>,
+}
+
+/// a BK-tree over image hashes of the same kind and size, finds the hashes
+/// within a distance without comparing against every indexed file
+#[derive(Debug, Default)]
+pub struct HashIndex {
+    nodes: Vec<Node>,
+    /// the node holding each indexed file
+    locations: HashMap<PathBuf, usize>,
+}
+
+impl HashIndex {
+    /// indexes the file, replacing its previous hash
+    pub fn insert(&mut self, path: PathBuf, hash: ImageHash) {
+        self.remove(&path);
+
+        let next = self.nodes.len();
+        let mut current = 0;
+        let node = loop {
+            let Some(node) = self.nodes.get_mut(current) else {
+                self.nodes.push(Node { hash, paths: Vec::new(), children: HashMap::new() });
+                break current;
+            };
+
+            let dist = node.hash.dist(&hash);
+            if dist == 0 {
+                break current;
+            }
+
+            match node.children.get(&dist) {
+                Some(&child) => current = child,
+                None => {
+                    node.children.insert(dist, next);
+                    self.nodes.push(Node { hash, paths: Vec::new(), children: HashMap::new() });
+                    break next;
+                }
+            }
+        };
+
+        self.nodes[node].paths.push(path.clone());
+        self.locations.insert(path, node);
+    }
+
+    pub fn remove(&mut self, path: &Path) {
+        if let Some(node) = self.locations.remove(path) {
+            self.nodes[node].paths.retain(|p| p != path);
+        }
+    }
+
+    /// the indexed files within `max_dist` of the hash, in no particular order
+    pub fn find(&self, hash: &ImageHash, max_dist: u32) -> Vec<(PathBuf, u32)> {
+        let mut found = Vec::new();
+        let mut stack = if self.nodes.is_empty() { Vec::new() } else { vec![0] };
+
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let dist = node.hash.dist(hash);
+            if dist <= max_dist {
+                found.extend(node.paths.iter().map(|path| (path.clone(), dist)));
+            }
+
+            // by the triangle inequality, matches only lie below children this close
+            let range = dist.saturating_sub(max_dist)..=dist.saturating_add(max_dist);
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| range.contains(d))
+                    .map(|(_, &child)| child),
+            );
+        }
+
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(bits: u64) -> ImageHash {
+        ImageHash::from_bytes(&bits.to_be_bytes()).unwrap()
+    }
+
+    fn sorted(mut found: Vec<(PathBuf, u32)>) -> Vec<(PathBuf, u32)> {
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn finds_hashes_within_distance() {
+        let mut index = HashIndex::default();
+        index.insert("a".into(), hash(0b0000));
+        index.insert("b".into(), hash(0b0001));
+        index.insert("c".into(), hash(0b0111));
+        index.insert("d".into(), hash(u64::MAX));
+
+        let found = sorted(index.find(&hash(0), 1));
+        assert_eq!(found, vec![("a".into(), 0), ("b".into(), 1)]);
+
+        let found = sorted(index.find(&hash(0b0011), 1));
+        assert_eq!(found, vec![("b".into(), 1), ("c".into(), 1)]);
+
+        assert_eq!(index.find(&hash(u64::MAX), 0), vec![("d".into(), 0)]);
+    }
+
+    #[test]
+    fn keeps_identical_hashes_apart() {
+        let mut index = HashIndex::default();
+        index.insert("a".into(), hash(1));
+        index.insert("b".into(), hash(1));
+        assert_eq!(sorted(index.find(&hash(1), 0)), vec![("a".into(), 0), ("b".into(), 0)]);
+    }
+
+    #[test]
+    fn updates_and_removes_files() {
+        let mut index = HashIndex::default();
+        index.insert("a".into(), hash(0));
+        index.insert("b".into(), hash(0xff));
+        index.insert("a".into(), hash(0xff));
+        assert!(index.find(&hash(0), 0).is_empty());
+        assert_eq!(index.find(&hash(0xff), 0).len(), 2);
+
+        index.remove(Path::new("b"));
+        assert_eq!(index.find(&hash(0xff), 0), vec![("a".into(), 0)]);
+    }
+}
@@ -0,0 +1,143 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard};
+use uuid::Uuid;
+
+use crate::store::now;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Remove,
+    Restore,
+    Purge,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub seq: u64,
+    pub operation: Operation,
+    /// the id of the file in the trash
+    pub id: String,
+    /// the original location of the file
+    pub path: PathBuf,
+    pub timestamp: u64,
+    /// operations made by a single request share the batch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub batch: Option<Uuid>,
+    /// the entry this one reverts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undo: Option<u64>,
+    /// the undone entry this one repeats
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redo: Option<u64>,
+}
+
+impl JournalEntry {
+    /// made by the user, as opposed to undo and redo
+    pub fn is_original(&self) -> bool {
+        self.undo.is_none() && self.redo.is_none()
+    }
+}
+
+/// the journal is held locked while an operation is made and recorded,
+/// so that undo and redo see a consistent history
+pub struct JournalLock<'a> {
+    journal: &'a Journal,
+    next_seq: MutexGuard<'a, u64>,
+}
+
+impl JournalLock<'_> {
+    pub fn append(
+        &mut self,
+        operation: Operation,
+        id: &str,
+        path: PathBuf,
+        batch: Option<Uuid>,
+        reverts: (Option<u64>, Option<u64>),
+    ) -> Result<()> {
+        let (undo, redo) = reverts;
+        let entry = JournalEntry {
+            seq: *self.next_seq,
+            operation,
+            id: id.to_owned(),
+            path,
+            timestamp: now(),
+            batch,
+            undo,
+            redo,
+        };
+
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.journal.path)?;
+        file.write_all(line.as_bytes())?;
+
+        *self.next_seq += 1;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<JournalEntry>> {
+        self.journal.entries()
+    }
+}
+
+/// append-only log of the trash operations, one JSON entry per line
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    next_seq: Mutex<u64>,
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        let journal = Self { path, next_seq: Mutex::new(0) };
+        match journal.entries() {
+            Ok(entries) => *journal.next_seq.lock().unwrap() = entries.last().map_or(0, |e| e.seq + 1),
+            Err(err) => tracing::error!("unable to read the journal: {:?}", err),
+        }
+        journal
+    }
+
+    pub fn lock(&self) -> JournalLock<'_> {
+        JournalLock { journal: self, next_seq: self.next_seq.lock().unwrap() }
+    }
+
+    fn entries(&self) -> Result<Vec<JournalEntry>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut entries = Vec::new();
+        for line in content.lines().filter(|line| !line.trim().is_empty()) {
+            // a line cut short by a crash shouldn't hide the rest of the history
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(err) => tracing::error!("skipping journal entry {:?}: {:?}", line, err),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// the original entries currently undone, keyed by their sequence number
+/// and mapped to the sequence number of the latest undo
+pub fn undone(entries: &[JournalEntry]) -> HashMap<u64, u64> {
+    let mut undone = HashMap::new();
+    for entry in entries {
+        if let Some(seq) = entry.undo {
+            undone.insert(seq, entry.seq);
+        }
+        if let Some(seq) = entry.redo {
+            undone.remove(&seq);
+        }
+    }
+    undone
+}
@@ -0,0 +1,207 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::analyzer::Groups;
+use crate::remover::Remover;
+use crate::scanner;
+
+/// files are compared in chunks of this size
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkKind {
+    #[default]
+    Hardlink,
+    /// a copy-on-write clone, only some file systems (btrfs, xfs, apfs) support it
+    Reflink,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linked {
+    pub path: PathBuf,
+    pub keeper: PathBuf,
+    /// the trash id of the original file, restoring it replaces the link again
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Skipped {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct LinkReport {
+    /// the journal batch of the trashed originals
+    pub batch: Uuid,
+    pub linked: Vec<Linked>,
+    pub skipped: Vec<Skipped>,
+    /// freed once the originals are purged from the trash
+    pub bytes: u64,
+}
+
+fn read_chunk(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        let n = file.read(&mut buf[total..])?;
+        if n == 0 {
+            break;
+        }
+        total += n;
+    }
+    Ok(total)
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0; CHUNK_SIZE], vec![0; CHUNK_SIZE]);
+    loop {
+        let n = read_chunk(&mut a, &mut buf_a)?;
+        let m = read_chunk(&mut b, &mut buf_b)?;
+        if buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+fn already_linked(a: &Path, b: &Path) -> io::Result<bool> {
+    let id = |path| fs::metadata(path).map(|metadata| scanner::file_id(&metadata));
+    Ok(id(a)?.is_some_and(|ida| Some(ida) == id(b).ok().flatten()))
+}
+
+/// the path still holds a link to the keeper: the same file for hardlinks,
+/// an identical copy for reflinks
+pub fn links_to(keeper: &Path, path: &Path) -> io::Result<bool> {
+    Ok(already_linked(keeper, path)? || same_content(keeper, path)?)
+}
+
+fn link(kind: LinkKind, keeper: &Path, path: &Path) -> io::Result<()> {
+    match kind {
+        LinkKind::Hardlink => fs::hard_link(keeper, path),
+        LinkKind::Reflink => reflink_copy::reflink(keeper, path),
+    }
+}
+
+/// replaces the file with a link to the keeper, the original is moved to the trash
+fn replace(kind: LinkKind, keeper: &Path, path: &Path, remover: &Remover, batch: Uuid) -> Result<String, String> {
+    if already_linked(keeper, path).map_err(|err| err.to_string())? {
+        return Err("already linked to the keeper".to_owned());
+    }
+    if !same_content(keeper, path).map_err(|err| err.to_string())? {
+        return Err("the content differs from the keeper".to_owned());
+    }
+
+    let id = remover.remove_linked(path, keeper, Some(batch)).map_err(|err| err.to_string())?;
+    if let Err(err) = link(kind, keeper, path) {
+        // put the original back, nothing is lost
+        if let Err(err) = remover.restore(&id, Some(batch)) {
+            tracing::error!(id, path = path.to_str(), "restore failed with: {:?}", err);
+        }
+        return Err(format!("unable to link: {}", err));
+    }
+
+    tracing::info!(path = path.to_str(), keeper = keeper.to_str(), "replaced with a link");
+    Ok(id)
+}
+
+/// replaces the byte-identical copies of every group's keeper with links to it,
+/// all paths stay in place while the space taken by the copies can be reclaimed
+pub fn link_duplicates(groups: &Groups, kind: LinkKind, remover: &Remover) -> LinkReport {
+    let mut report = LinkReport { batch: Uuid::new_v4(), ..Default::default() };
+
+    for group in groups {
+        let Some(keeper) = group.keeper() else {
+            continue;
+        };
+
+        for member in group.files.iter().filter(|m| m.file.path != keeper.file.path) {
+            let path = &member.file.path;
+            match replace(kind, &keeper.file.path, path, remover, report.batch) {
+                Ok(id) => {
+                    report.bytes += member.file.size;
+                    report.linked.push(Linked { path: path.clone(), keeper: keeper.file.path.clone(), id });
+                }
+                Err(reason) => report.skipped.push(Skipped { path: path.clone(), reason }),
+            }
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{FileInfo, Group, Member};
+    use crate::remover::Selection;
+    use tempfile::TempDir;
+
+    fn member(path: PathBuf, date: u64) -> Member {
+        let size = fs::metadata(&path).unwrap().len();
+        let file = FileInfo { path, size, date, modified: date, reference: false, root: None, links: Vec::new() };
+        Member { file, score: None, dist: None }
+    }
+
+    fn remover(dir: &TempDir) -> Remover {
+        fs::create_dir(dir.path().join("trash")).unwrap();
+        Remover::new(dir.path().join("trash"))
+    }
+
+    #[test]
+    fn links_identical_copies_only() {
+        let dir = TempDir::new().unwrap();
+        let remover = remover(&dir);
+
+        let [keeper, copy, other] = ["keeper.png", "copy.png", "other.png"].map(|name| dir.path().join(name));
+        fs::write(&keeper, "same").unwrap();
+        fs::write(&copy, "same").unwrap();
+        fs::write(&other, "diff").unwrap();
+        let files = vec![member(keeper.clone(), 1), member(copy.clone(), 2), member(other.clone(), 3)];
+        let groups = vec![Group { files, status: None }];
+
+        let report = link_duplicates(&groups, LinkKind::Hardlink, &remover);
+        assert_eq!(report.linked.len(), 1);
+        assert_eq!((report.linked[0].path.clone(), report.bytes), (copy.clone(), 4));
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.skipped[0].path, other);
+        assert!(already_linked(&keeper, &copy).unwrap());
+        assert!(!already_linked(&keeper, &other).unwrap());
+
+        let report = link_duplicates(&groups, LinkKind::Hardlink, &remover);
+        assert!(report.linked.is_empty());
+        assert_eq!(report.skipped[0].reason, "already linked to the keeper");
+    }
+
+    #[test]
+    fn restoring_the_original_replaces_the_link() {
+        let dir = TempDir::new().unwrap();
+        let remover = remover(&dir);
+        let [keeper, copy] = ["keeper.png", "copy.png"].map(|name| dir.path().join(name));
+        fs::write(&keeper, "same").unwrap();
+        fs::write(&copy, "same").unwrap();
+        let groups = vec![Group { files: vec![member(keeper.clone(), 1), member(copy.clone(), 2)], status: None }];
+
+        let report = link_duplicates(&groups, LinkKind::Hardlink, &remover);
+        assert_eq!(remover.restore(&report.linked[0].id, None).unwrap(), copy);
+        assert!(!already_linked(&keeper, &copy).unwrap());
+
+        let report = link_duplicates(&groups, LinkKind::Hardlink, &remover);
+        let reverted = remover.undo(Selection::Batch(report.batch)).unwrap();
+        assert_eq!(reverted.len(), 1);
+        assert!(!already_linked(&keeper, &copy).unwrap());
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "same");
+    }
+}
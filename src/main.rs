@@ -1,21 +1,48 @@
 mod analyzer;
+mod linker;
 mod manager;
+mod plan;
 mod cache;
+mod cli;
 mod disjoint_set;
+mod error;
+mod export;
+mod histogram;
+mod index;
+mod journal;
+mod regions;
 mod remover;
-
-use analyzer::{Analyzer, AnalyzeRequest, Groups, FileInfo};
+mod results;
+mod scanner;
+mod snapshot;
+mod store;
+mod thumbnail;
+mod verifier;
+mod watcher;
+
+use error::{Error, ErrorBody};
+use export::Format;
+use thumbnail::THUMBNAIL_SIZE;
+use analyzer::{Analysis, Analyzer, AnalyzeRequest, FileInfo, Progress, SearchRequest, SearchMatch};
 use manager::{TaskManager, TaskResponse};
-use remover::{Remover, RemovedFile};
+use linker::{LinkKind, LinkReport};
+use plan::PlanReport;
+use remover::{HistoryEntry, Issue, RemoveReport, Remover, RestoreReport, RestoreRequest, Reverted, Selection, TrashFilter, TrashPage, TrashQuery};
+use results::{GroupEntry, GroupQuery, Summary};
+use scanner::ScanOptions;
+use store::{TaskStore, TaskSummary};
+use watcher::{DuplicateFound, WatchConfig};
 use tracing::Span;
 use std::{
+    collections::{BTreeSet, HashMap},
     path::PathBuf,
     sync::Arc, time::{Instant, Duration}, convert::Infallible,
 };
 use serde::{Serialize, Deserialize};
 use eyre::{Result, Report};
 use axum::{
-    http::{Request, StatusCode, Response},
+    body::Bytes,
+    http::{header, Request, Response},
     extract::{Query, State, Path},
     routing::{get, get_service, post},
     response::{
@@ -24,46 +51,166 @@ use axum::{
     },
     Router,
 };
+// accepts repeated keys, e.g. `exclude=a&exclude=b`
+use axum_extra::extract::Query as MultiQuery;
 use tower::ServiceExt;
 use tower_http::{
     services,
     trace::TraceLayer,
 };
 use tokio::{
-    task::JoinHandle,
-    sync::{mpsc, oneshot, watch},
+    task::{self, JoinHandle},
+    sync::{broadcast, mpsc, oneshot, watch},
 };
-use futures::stream::{Stream, StreamExt};
-use tokio_stream::wrappers::WatchStream;
+use futures::{future::Either, stream::{Stream, StreamExt}};
+use tokio_stream::wrappers::{BroadcastStream, WatchStream};
 use uuid::Uuid;
 
-type TaskResult = Result<Groups>;
+type TaskResult = Result<Arc<Analysis>>;
 
 enum AnalyzeCommand {
-    Submit(AnalyzeRequest, oneshot::Sender<Uuid>),
-    Subscribe(Uuid, oneshot::Sender<Option<watch::Receiver<usize>>>),
-    Poll(Uuid, oneshot::Sender<Option<TaskResponse<usize, TaskResult>>>),
+    Submit(AnalyzeRequest, i32, oneshot::Sender<Uuid>),
+    Subscribe(Uuid, oneshot::Sender<Option<watch::Receiver<Progress>>>),
+    Poll(Uuid, oneshot::Sender<Option<TaskResponse<Progress, Arc<TaskResult>>>>),
+    Result(Uuid, oneshot::Sender<Option<Arc<TaskResult>>>),
+    /// answered once the task completes
+    Wait(Uuid, oneshot::Sender<Option<Arc<TaskResult>>>),
+    /// hands over a result loaded from the store, see `ask_task`
+    Complete(Uuid, TaskResult),
+    Search(SearchRequest, Bytes, oneshot::Sender<Result<Vec<SearchMatch>>>),
+}
+
+type Manager = TaskManager<Uuid, Progress, TaskResult>;
+
+struct AnalyzerConfig {
+    /// re-run the tasks interrupted by the last shutdown
+    resume: bool,
+    /// analyses running at the same time, the rest wait in the queue
+    max_tasks: usize,
+    /// hashing threads of a single analysis
+    task_threads: usize,
+    /// how long completed results stay in memory since last requested,
+    /// they are loaded back from the store afterwards
+    result_ttl: Duration,
+}
+
+impl AnalyzerConfig {
+    fn from_env() -> Result<Self> {
+        let var = |name| std::env::var(name).ok().map(|value| value.parse::<usize>()).transpose();
+
+        let max_tasks = var("IMAGE_ANALYZER_MAX_TASKS")?.unwrap_or(2).max(1);
+        let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+        // share the cores between the running analyses by default
+        let task_threads = var("IMAGE_ANALYZER_TASK_THREADS")?.unwrap_or(cpus / max_tasks).max(1);
+
+        Ok(Self {
+            resume: std::env::var_os("IMAGE_ANALYZER_RESUME").is_some(),
+            max_tasks,
+            task_threads,
+            result_ttl: Duration::from_secs(var("IMAGE_ANALYZER_RESULT_TTL")?.unwrap_or(600) as u64),
+        })
+    }
+}
+
+fn submit_task(
+    manager: &mut Manager,
+    engine: &Arc<Analyzer>,
+    store: &Arc<TaskStore>,
+    threads: usize,
+    task_id: Uuid,
+    req: AnalyzeRequest,
+    priority: i32,
+) {
+    tracing::info!("analyze task {:?} submitted", req);
+    if let Err(err) = store.save_request(task_id, req.clone()) {
+        tracing::error!("unable to store task {:?}: {:?}", task_id, err);
+    }
+
+    let engine = engine.clone();
+    let store = store.clone();
+    manager.submit(task_id, priority, move |tx| {
+        let started = Instant::now();
+        let result = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(eyre::Report::from)
+            .and_then(|pool| engine.analyze(&req, &pool, tx))
+            .map(Arc::new);
+        let elapsed = started.elapsed();
+        tracing::info!("analyze task {:?} completed in {:?}", req, elapsed);
+        if let Err(err) = store.save_result(&task_id, &result) {
+            tracing::error!("unable to store result of task {:?}: {:?}", task_id, err);
+        }
+        result
+    });
 }
 
-async fn task_analyzer(mut rx: mpsc::Receiver<AnalyzeCommand>) {
+
+async fn task_analyzer(
+    engine: Arc<Analyzer>,
+    store: Arc<TaskStore>,
+    config: AnalyzerConfig,
+    mut rx: mpsc::Receiver<AnalyzeCommand>,
+) {
     tracing::info!("manager task started");
 
-    let engine = Arc::new(Analyzer::new());
-    let mut manager: TaskManager<Uuid, usize, TaskResult> = TaskManager::new();
+    let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+    let mut manager: Manager = TaskManager::new(config.max_tasks, config.result_ttl, done_tx);
+    let threads = config.task_threads;
+    // requests of the queued and running tasks, an equivalent request joins the task
+    let mut in_flight: Vec<(AnalyzeRequest, Uuid)> = Vec::new();
+    // clients waiting for queued and running tasks to complete
+    let mut waiting: HashMap<Uuid, Vec<oneshot::Sender<Option<Arc<TaskResult>>>>> = HashMap::new();
+
+    if config.resume {
+        match store.interrupted() {
+            Ok(tasks) => {
+                for task in tasks {
+                    tracing::info!("resuming analyze task {:?}", task.id);
+                    in_flight.push((task.request.clone(), task.id));
+                    submit_task(&mut manager, &engine, &store, threads, task.id, task.request, 0);
+                }
+            }
+            Err(err) => tracing::error!("unable to list interrupted tasks: {:?}", err),
+        }
+    }
+
+    loop {
+        let command = tokio::select! {
+            command = rx.recv() => match command {
+                Some(command) => command,
+                None => break,
+            },
+            Some(task_id) = done_rx.recv() => {
+                let result = manager.task_done(&task_id).await;
+                in_flight.retain(|(_, id)| *id != task_id);
+                for tx in waiting.remove(&task_id).unwrap_or_default() {
+                    if tx.send(result.clone()).is_err() {
+                        tracing::error!("unable to send response back to the client");
+                    }
+                }
+                continue;
+            }
+        };
 
-    while let Some(command) = rx.recv().await {
         match command {
-            AnalyzeCommand::Submit(req, tx) => {
-                tracing::info!("analyze task {:?} submitted", req);
-                let engine = engine.clone();
-                let task_id = Uuid::new_v4();
-                manager.submit(task_id, move |tx| {
-                    let started = Instant::now();
-                    let result = engine.analyze(&req, tx);
-                    let elapsed = started.elapsed();
-                    tracing::info!("analyze task {:?} completed in {:?}", req, elapsed);
-                    result
-                });
+            AnalyzeCommand::Submit(mut req, priority, tx) => {
+                // the order of the folders doesn't affect the result
+                req.paths.sort();
+                req.paths.dedup();
+
+                let task_id = match in_flight.iter().find(|(running, _)| *running == req) {
+                    Some((_, task_id)) => {
+                        tracing::info!("analyze task {:?} is already running", task_id);
+                        *task_id
+                    }
+                    None => {
+                        let task_id = Uuid::new_v4();
+                        in_flight.push((req.clone(), task_id));
+                        submit_task(&mut manager, &engine, &store, threads, task_id, req, priority);
+                        task_id
+                    }
+                };
                 if tx.send(task_id).is_err() {
                     tracing::error!("unable to send response back to the client");
                 }
@@ -80,45 +227,72 @@ async fn task_analyzer(mut rx: mpsc::Receiver<AnalyzeCommand>) {
                     tracing::error!("unable to send response back to the client");
                 }
             }
+            AnalyzeCommand::Result(task_id, tx) => {
+                let resp = manager.result(&task_id).await;
+                if tx.send(resp).is_err() {
+                    tracing::error!("unable to send response back to the client");
+                }
+            }
+            AnalyzeCommand::Wait(task_id, tx) => {
+                let resp = match manager.poll(&task_id).await {
+                    Some(TaskResponse::Queued(_) | TaskResponse::Pending(_)) => {
+                        waiting.entry(task_id).or_default().push(tx);
+                        continue;
+                    }
+                    Some(TaskResponse::Completed(result)) => Some(result),
+                    None => None,
+                };
+                if tx.send(resp).is_err() {
+                    tracing::error!("unable to send response back to the client");
+                }
+            }
+            AnalyzeCommand::Complete(task_id, result) => {
+                // the task may have been submitted again meanwhile
+                if !manager.contains(&task_id) {
+                    manager.complete(task_id, result);
+                }
+            }
+            AnalyzeCommand::Search(req, data, tx) => {
+                let engine = engine.clone();
+                task::spawn_blocking(move || {
+                    let resp = engine.search(&req, &data);
+                    if tx.send(resp).is_err() {
+                        tracing::error!("unable to send response back to the client");
+                    }
+                });
+            }
         }
     }
 
     tracing::info!("manager task exiting");
 }
 
-fn spawn_analyzer() -> (JoinHandle<()>, mpsc::Sender<AnalyzeCommand>) {
+fn spawn_analyzer(
+    engine: Arc<Analyzer>,
+    store: Arc<TaskStore>,
+    config: AnalyzerConfig,
+) -> (JoinHandle<()>, mpsc::Sender<AnalyzeCommand>) {
     let (tx, rx) = mpsc::channel(32);
-    let join_handle = tokio::spawn(task_analyzer(rx));
+    let join_handle = tokio::spawn(task_analyzer(engine, store, config, rx));
     (join_handle, tx)
 }
 
-enum AppError {
-    Internal(Report),
-    Provided(StatusCode),
-}
-
-impl AppError {
-    fn not_found() -> Self {
-        Self::Provided(StatusCode::NOT_FOUND)
-    }
-}
+/// wraps any error, typed ones (see `error::Error`) are reported to the client in detail
+struct AppError(Report);
 
 impl<T> From<T> for AppError
 where
     T: Into<Report>
 {
     fn from(inner: T) -> Self {
-        Self::Internal(inner.into())
+        Self(inner.into())
     }
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
-        let status_code = match self {
-            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Self::Provided(code) => code,
-        };
-        status_code.into_response()
+        let (status_code, body) = ErrorBody::new(&self.0);
+        (status_code, Json(body)).into_response()
     }
 }
 
@@ -128,14 +302,36 @@ type JsonResponse<T> = AppResult<Json<T>>;
 struct AppState {
     task_sender: mpsc::Sender<AnalyzeCommand>,
     remover: Remover,
+    store: Arc<TaskStore>,
+    duplicates: broadcast::Sender<DuplicateFound>,
 }
 
 #[derive(Serialize)]
 #[serde(tag = "type")]
 enum AnalyzeResponse {
-    Pending { progress: usize },
-    Completed { data: Groups },
-    Failed { error: String },
+    Queued { position: usize },
+    Pending { progress: Progress },
+    /// `data` is a page of `total` groups, `removed` the groups of the previous
+    /// analysis with the same parameters whose files aren't grouped anymore
+    Completed {
+        data: Vec<GroupEntry>,
+        total: usize,
+        summary: Summary,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        removed: Vec<BTreeSet<PathBuf>>,
+    },
+    Failed { code: &'static str, error: String },
+}
+
+/// the body of an analysis request, the priority only orders the queue
+/// so equivalent requests are joined whatever their priority
+#[derive(Deserialize)]
+struct AnalyzeSubmission {
+    #[serde(flatten)]
+    request: AnalyzeRequest,
+    /// queued tasks with a higher priority start first
+    #[serde(default)]
+    priority: i32,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -151,16 +347,19 @@ struct TaskParams {
 
 fn check_path(path: &std::path::Path) -> AppResult<()> {
     if !path.is_dir() {
-        Err(AppError::not_found())
+        Err(Error::FolderNotFound(path.to_owned()).into())
     } else {
         Ok(())
     }
 }
 
-async fn list_folder(Query(params): Query<PathParams>) -> JsonResponse<Vec<FileInfo>> {
+async fn list_folder(
+    Query(params): Query<PathParams>,
+    MultiQuery(options): MultiQuery<ScanOptions>,
+) -> JsonResponse<Vec<FileInfo>> {
     check_path(&params.path)?;
 
-    let files = analyzer::list_dir(&params.path)?;
+    let files = scanner::list_dir(&params.path, &options)?;
     Ok(Json(files))
 }
 
@@ -168,7 +367,7 @@ async fn delete_file(
     State(state): State<Arc<AppState>>,
     Query(params): Query<PathParams>,
 ) -> JsonResponse<String> {
-    let base_name = state.remover.remove(&params.path)?;
+    let base_name = state.remover.remove(&params.path, None)?;
     Ok(Json(base_name))
 }
 
@@ -178,35 +377,139 @@ async fn restore_file(
 ) -> JsonResponse<PathBuf> {
     // TODO: check id
 
-    let path = state.remover.restore(&id)?;
+    let path = state.remover.restore(&id, None)?;
     Ok(Json(path))
 }
 
-async fn restore_all(
+/// confirms the removal, the file can't be restored afterwards
+async fn purge_file(
     State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
 ) -> AppResult<()> {
-    state.remover.restore_all()?;
+    state.remover.purge(&id, None)?;
     Ok(())
 }
 
+async fn restore_all(
+    State(state): State<Arc<AppState>>,
+) -> JsonResponse<RestoreReport> {
+    let report = task::spawn_blocking(move || state.remover.restore_all()).await?;
+    Ok(Json(report))
+}
+
+async fn restore_selected(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<RestoreRequest>,
+) -> JsonResponse<RestoreReport> {
+    let report = task::spawn_blocking(move || state.remover.restore_selected(req)).await??;
+    Ok(Json(report))
+}
+
+async fn check_trash(
+    State(state): State<Arc<AppState>>,
+) -> JsonResponse<Vec<Issue>> {
+    let issues = task::spawn_blocking(move || state.remover.check(false)).await??;
+    Ok(Json(issues))
+}
+
+async fn repair_trash(
+    State(state): State<Arc<AppState>>,
+) -> JsonResponse<Vec<Issue>> {
+    let issues = task::spawn_blocking(move || state.remover.check(true)).await??;
+    Ok(Json(issues))
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    limit: Option<usize>,
+}
+
+async fn history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+) -> JsonResponse<Vec<HistoryEntry>> {
+    let history = state.remover.history(params.limit)?;
+    Ok(Json(history))
+}
+
+/// selects either a batch or the given number of the latest operations, one by default
+#[derive(Deserialize)]
+struct RevertParams {
+    count: Option<usize>,
+    batch: Option<Uuid>,
+}
+
+impl RevertParams {
+    fn selection(&self) -> Selection {
+        match self.batch {
+            Some(batch) => Selection::Batch(batch),
+            None => Selection::Last(self.count.unwrap_or(1)),
+        }
+    }
+}
+
+async fn undo(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RevertParams>,
+) -> JsonResponse<Vec<Reverted>> {
+    let reverted = state.remover.undo(params.selection())?;
+    Ok(Json(reverted))
+}
+
+async fn redo(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<RevertParams>,
+) -> JsonResponse<Vec<Reverted>> {
+    let reverted = state.remover.redo(params.selection())?;
+    Ok(Json(reverted))
+}
+
 async fn list_deleted(
     State(state): State<Arc<AppState>>,
-) -> JsonResponse<Vec<RemovedFile>> {
-    let files = state.remover.list_removed()?;
-    Ok(Json(files))
+    Query(filter): Query<TrashFilter>,
+    Query(query): Query<TrashQuery>,
+) -> JsonResponse<TrashPage> {
+    let page = state.remover.list_removed(&filter, &query);
+    Ok(Json(page))
+}
+
+#[derive(Deserialize)]
+struct PlanParams {
+    /// trash the files, only check the plan otherwise
+    #[serde(default)]
+    apply: bool,
+}
+
+/// trashes the files marked in a deletion plan, see `plan::run`
+async fn apply_plan(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<PlanParams>,
+    body: Bytes,
+) -> JsonResponse<PlanReport> {
+    let report = task::spawn_blocking(move || plan::run(&body, &state.remover, !params.apply)).await??;
+    Ok(Json(report))
 }
 
 async fn analyze(
     State(state): State<Arc<AppState>>,
-    Query(req): Query<AnalyzeRequest>,
+    Json(AnalyzeSubmission { request: req, priority }): Json<AnalyzeSubmission>,
 ) -> JsonResponse<TaskParams> {
-    check_path(&req.path)?;
+    if req.paths.is_empty() {
+        return Err(Error::BadRequest("no folders to analyze".to_owned()).into());
+    }
+    req.validate()?;
+    for path in &req.paths {
+        check_path(path)?;
+    }
+    if let Some(reference) = &req.reference {
+        check_path(reference)?;
+    }
 
     let (tx, rx) = oneshot::channel();
 
     state
         .task_sender
-        .send(AnalyzeCommand::Submit(req, tx))
+        .send(AnalyzeCommand::Submit(req, priority, tx))
         .await?;
 
     let task_id = rx.await?;
@@ -217,21 +520,213 @@ async fn analyze(
 async fn poll(
     State(state): State<Arc<AppState>>,
     Query(params): Query<TaskParams>,
+    Query(query): Query<GroupQuery>,
 ) -> JsonResponse<AnalyzeResponse> {
+    let task_id = params.task_id;
+    let resp = ask_task(&state, task_id, |tx| AnalyzeCommand::Poll(task_id, tx)).await?;
+    let resp = resp.ok_or(Error::TaskNotFound(task_id))?;
+    Ok(Json(match resp {
+        TaskResponse::Queued(position) => AnalyzeResponse::Queued { position },
+        TaskResponse::Pending(progress) => AnalyzeResponse::Pending { progress },
+        TaskResponse::Completed(result) => match result.as_ref() {
+            Ok(analysis) => {
+                let groups = &analysis.groups;
+                let data = results::page(groups, &query).into_iter().map(|i| GroupEntry::new(&groups[i])).collect();
+                let removed = analysis.removed.clone();
+                AnalyzeResponse::Completed { data, total: groups.len(), summary: Summary::new(groups), removed }
+            }
+            Err(err) => {
+                let err = task_error(params.task_id, err);
+                AnalyzeResponse::Failed { code: err.code(), error: err.to_string() }
+            }
+        }
+    }))
+}
+
+async fn search(
+    State(state): State<Arc<AppState>>,
+    Query(req): Query<SearchRequest>,
+    body: Bytes,
+) -> JsonResponse<Vec<SearchMatch>> {
+    req.validate()?;
+    if let Some(path) = &req.path {
+        if !path.is_file() {
+            return Err(Error::FileNotFound(path.clone()).into());
+        }
+    }
+
     let (tx, rx) = oneshot::channel();
 
     state
         .task_sender
-        .send(AnalyzeCommand::Poll(params.task_id, tx))
+        .send(AnalyzeCommand::Search(req, body, tx))
         .await?;
 
-    let resp = rx.await?;
-    let resp = resp.ok_or_else(AppError::not_found)?;
-    Ok(Json(match resp {
-        TaskResponse::Pending(progress) => AnalyzeResponse::Pending { progress },
-        TaskResponse::Completed(Ok(data)) => AnalyzeResponse::Completed { data },
-        TaskResponse::Completed(Err(err)) => AnalyzeResponse::Failed { error: err.to_string() }
-    }))
+    let matches = rx.await??;
+    Ok(Json(matches))
+}
+
+/// the error reported for a task that didn't produce groups
+fn task_error(task_id: Uuid, err: &Report) -> Error {
+    match err.downcast_ref::<Error>() {
+        Some(Error::TaskInterrupted(id)) => Error::TaskInterrupted(*id),
+        Some(Error::TaskFailed(id, message)) => Error::TaskFailed(*id, message.clone()),
+        Some(Error::TaskAborted(message)) => Error::TaskAborted(message.clone()),
+        _ => Error::TaskFailed(task_id, err.to_string()),
+    }
+}
+
+fn analysis_of(task_id: Uuid, result: Option<Arc<TaskResult>>) -> AppResult<Arc<Analysis>> {
+    let result = result.ok_or(Error::TaskNotFound(task_id))?;
+    match result.as_ref() {
+        Ok(analysis) => Ok(analysis.clone()),
+        Err(err) => Err(task_error(task_id, err).into()),
+    }
+}
+
+/// sends a command about the task to the manager; a task it doesn't know about
+/// is looked up among the stored ones, read here rather than on the manager task
+async fn ask_task<T, F>(state: &AppState, task_id: Uuid, command: F) -> Result<Option<T>>
+where
+    F: Fn(oneshot::Sender<Option<T>>) -> AnalyzeCommand,
+{
+    let (tx, rx) = oneshot::channel();
+    state.task_sender.send(command(tx)).await?;
+    if let Some(resp) = rx.await? {
+        return Ok(Some(resp));
+    }
+
+    let store = state.store.clone();
+    let Some(result) = task::spawn_blocking(move || store.load_result(&task_id)).await? else {
+        return Ok(None);
+    };
+    state.task_sender.send(AnalyzeCommand::Complete(task_id, result.map(Arc::new))).await?;
+
+    let (tx, rx) = oneshot::channel();
+    state.task_sender.send(command(tx)).await?;
+    Ok(rx.await?)
+}
+
+/// returns the result of a successfully completed task
+async fn task_analysis(state: &AppState, task_id: Uuid) -> AppResult<Arc<Analysis>> {
+    let result = ask_task(state, task_id, |tx| AnalyzeCommand::Result(task_id, tx)).await?;
+    analysis_of(task_id, result)
+}
+
+/// waits for a queued or running task to complete
+async fn wait_analysis(state: &AppState, task_id: Uuid) -> AppResult<Arc<Analysis>> {
+    let result = ask_task(state, task_id, |tx| AnalyzeCommand::Wait(task_id, tx)).await?;
+    analysis_of(task_id, result)
+}
+
+async fn task_summary(
+    State(state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+) -> JsonResponse<Summary> {
+    let analysis = task_analysis(&state, task_id).await?;
+    Ok(Json(Summary::new(&analysis.groups)))
+}
+
+/// waits for the analysis to complete, then sends the requested page of groups
+/// one `group` event at a time, followed by the `summary` event; the groups
+/// are only known once the whole analysis is over, keep-alives are sent meanwhile.
+/// A task that fails or doesn't exist ends the stream with an `error` event
+async fn stream_groups(
+    State(state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+    Query(query): Query<GroupQuery>,
+) -> Sse<impl Stream<Item = serde_json::error::Result<Event>>> {
+    let analysis = async move { wait_analysis(&state, task_id).await };
+    let stream = futures::stream::once(analysis).flat_map(move |analysis| match analysis {
+        Ok(analysis) => {
+            let indices = results::page(&analysis.groups, &query);
+            let summary = Event::default().event("summary").json_data(Summary::new(&analysis.groups));
+            let events = futures::stream::iter(indices)
+                .map(move |i| Event::default().event("group").json_data(GroupEntry::new(&analysis.groups[i])))
+                .chain(futures::stream::once(async { summary }));
+            Either::Left(events)
+        }
+        Err(AppError(err)) => {
+            let (_, body) = ErrorBody::new(&err);
+            Either::Right(futures::stream::once(async move { Event::default().event("error").json_data(body) }))
+        }
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// moves to the recycle bin every analyzed file that has
+/// a duplicate in the reference collection
+async fn remove_candidates(
+    State(state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+) -> JsonResponse<RemoveReport> {
+    let analysis = task_analysis(&state, task_id).await?;
+
+    let candidates = analysis
+        .groups
+        .iter()
+        .filter(|group| group.files.iter().any(|m| m.file.reference))
+        .flat_map(|group| &group.files)
+        .filter(|m| !m.file.reference)
+        .map(|m| m.file.path.clone())
+        .collect();
+
+    let report = task::spawn_blocking(move || state.remover.remove_all(candidates)).await?;
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: Format,
+}
+
+async fn export_task(
+    State(state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+    Query(params): Query<ExportParams>,
+) -> AppResult<impl IntoResponse> {
+    let analysis = task_analysis(&state, task_id).await?;
+    let format = params.format;
+
+    // rendering thumbnails takes a while
+    let content = task::spawn_blocking(move || export::export(&analysis.groups, format, &format!("Analysis {}", task_id)))
+        .await??;
+
+    let disposition = format!("attachment; filename=\"{}.{}\"", task_id, format.extension());
+    let headers = [
+        (header::CONTENT_TYPE, format.content_type().to_owned()),
+        (header::CONTENT_DISPOSITION, disposition),
+    ];
+    Ok((headers, content))
+}
+
+#[derive(Deserialize)]
+struct LinkParams {
+    #[serde(default)]
+    kind: LinkKind,
+}
+
+/// replaces exact duplicates with links to the keeper of their group,
+/// the originals stay in the recycle bin until purged
+async fn link_duplicates(
+    State(state): State<Arc<AppState>>,
+    Path(task_id): Path<Uuid>,
+    Query(params): Query<LinkParams>,
+) -> JsonResponse<LinkReport> {
+    let analysis = task_analysis(&state, task_id).await?;
+
+    let report = task::spawn_blocking(move || linker::link_duplicates(&analysis.groups, params.kind, &state.remover))
+        .await?;
+
+    Ok(Json(report))
+}
+
+async fn list_tasks(
+    State(state): State<Arc<AppState>>,
+) -> JsonResponse<Vec<TaskSummary>> {
+    let tasks = state.store.list_summaries()?;
+    Ok(Json(tasks))
 }
 
 async fn subscribe(
@@ -248,11 +743,22 @@ async fn subscribe(
         .await?;
 
     let resp = rx.await?;
-    let resp = resp.ok_or_else(AppError::not_found)?;
+    let resp = resp.ok_or(Error::TaskNotFound(params.task_id))?;
     let stream = WatchStream::new(resp).map(|p| Event::default().json_data(p));
     Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
 }
 
+/// notifies about duplicates of images appearing in the watched folders
+async fn watch_duplicates(
+    State(state): State<Arc<AppState>>,
+) -> Sse<impl Stream<Item = serde_json::error::Result<Event>>> {
+    let stream = BroadcastStream::new(state.duplicates.subscribe())
+        // lagging subscribers miss some notifications
+        .filter_map(|event| async move { event.ok() })
+        .map(|event| Event::default().json_data(event));
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
 type FileResponse = Response<tower_http::services::fs::ServeFileSystemResponseBody>;
 
 async fn serve_image<T>(
@@ -274,20 +780,76 @@ async fn serve_deleted<T>(
 where
     T: Send + 'static
 {
-    let path = state.remover.resolve(&id)?;
-    let service = services::ServeFile::new(&path);
+    let (path, mime) = state.remover.open(&id)?;
+    let service = services::ServeFile::new_with_mime(&path, &mime);
     let response = service.oneshot(request).await?;
     Ok(response)
 }
 
+#[derive(Deserialize)]
+struct ThumbnailParams {
+    /// up to `MAX_THUMBNAIL_SIZE`
+    size: Option<u32>,
+}
+
+/// larger thumbnails would cost about as much as the image itself
+const MAX_THUMBNAIL_SIZE: u32 = THUMBNAIL_SIZE * 8;
+
+async fn render_thumbnail(path: PathBuf, size: Option<u32>) -> AppResult<impl IntoResponse> {
+    let size = size.unwrap_or(THUMBNAIL_SIZE);
+    if !(1..=MAX_THUMBNAIL_SIZE).contains(&size) {
+        let message = format!("the thumbnail size must be between 1 and {}", MAX_THUMBNAIL_SIZE);
+        return Err(Error::BadRequest(message).into());
+    }
+    let data = task::spawn_blocking(move || thumbnail::thumbnail(&path, size)).await??;
+    Ok(([(header::CONTENT_TYPE, "image/jpeg")], data))
+}
+
+async fn serve_thumbnail(
+    Query(params): Query<PathParams>,
+    Query(thumbnail): Query<ThumbnailParams>,
+) -> AppResult<impl IntoResponse> {
+    render_thumbnail(params.path, thumbnail.size).await
+}
+
+async fn serve_deleted_thumbnail(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    Query(thumbnail): Query<ThumbnailParams>,
+) -> AppResult<impl IntoResponse> {
+    let (path, _) = state.remover.open(&id)?;
+    render_thumbnail(path, thumbnail.size).await
+}
+
+/// analysis results are kept here
+const RESULTS_ROOT: &str = "results";
+/// removed files are moved here
+const REMOVED_ROOT: &str = "removed";
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        return cli::run(&args);
+    }
+
     tracing_subscriber::fmt().init();
     tracing::info!("starting...");
 
-    let (_, task_sender) = spawn_analyzer();
-    let remover = Remover::new("removed");
-    let shared_state = Arc::new(AppState { task_sender, remover });
+    let engine = Arc::new(Analyzer::new());
+    let (duplicates, _) = broadcast::channel(32);
+
+    // folders to keep hashes up to date for
+    if let Some(config) = WatchConfig::from_env()? {
+        watcher::spawn(engine.clone(), config, duplicates.clone())?;
+    }
+
+    let config = AnalyzerConfig::from_env()?;
+    let store = Arc::new(TaskStore::new(RESULTS_ROOT));
+
+    let (_, task_sender) = spawn_analyzer(engine, store.clone(), config);
+    let remover = Remover::new(REMOVED_ROOT);
+    let shared_state = Arc::new(AppState { task_sender, remover, store, duplicates });
 
     let http_logger = TraceLayer::new_for_http()
         .make_span_with(|req: &Request<_>| {
@@ -313,15 +875,32 @@ async fn main() -> Result<()> {
     let app = Router::new()
         .route("/", get_service(services::ServeFile::new("client/dist/index.html")))
         .route("/image", get(serve_image))
+        .route("/thumbnail", get(serve_thumbnail))
         .route("/list_folder", get(list_folder))
         .route("/delete_file", post(delete_file))
         .route("/deleted", get(list_deleted))
-        .route("/deleted/:id", get(serve_deleted))
+        .route("/deleted/:id", get(serve_deleted).delete(purge_file))
         .route("/deleted/:id/restore", post(restore_file))
+        .route("/deleted/:id/thumbnail", get(serve_deleted_thumbnail))
+        .route("/deleted/restore", post(restore_selected))
         .route("/deleted/restore_all", post(restore_all))
+        .route("/deleted/check", get(check_trash))
+        .route("/deleted/repair", post(repair_trash))
+        .route("/history", get(history))
+        .route("/history/undo", post(undo))
+        .route("/history/redo", post(redo))
+        .route("/plan", post(apply_plan))
         .route("/analyze", post(analyze))
         .route("/poll", get(poll))
+        .route("/tasks", get(list_tasks))
+        .route("/tasks/:id/export", get(export_task))
+        .route("/tasks/:id/summary", get(task_summary))
+        .route("/tasks/:id/stream", get(stream_groups))
+        .route("/tasks/:id/link", post(link_duplicates))
         .route("/subscribe", get(subscribe))
+        .route("/search", post(search))
+        .route("/watch", get(watch_duplicates))
+        .route("/tasks/:id/remove_candidates", post(remove_candidates))
         .nest_service("/static", services::ServeDir::new("client/dist"))
         .nest_service("/assets", services::ServeDir::new("client/dist/assets"))
         .with_state(shared_state)
@@ -1,61 +1,284 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     hash::Hash,
+    sync::Arc,
+    time::{Duration, Instant},
 };
 use tokio::{
-    task::{self, JoinHandle},
-    sync::watch,
+    task::{self, JoinError, JoinHandle},
+    sync::{mpsc, watch},
 };
 
+use crate::error::Error;
+
 pub enum TaskResponse<P, R> {
+    /// waiting for a free slot, the position in the queue starts from 1
+    Queued(usize),
     Pending(P),
     Completed(R),
 }
 
+type Job<P, R> = Box<dyn FnOnce(watch::Sender<P>) -> R + Send>;
+
+/// task results able to tell that the task didn't run to completion
+pub trait Outcome {
+    fn aborted(err: JoinError) -> Self;
+}
+
+impl<T> Outcome for eyre::Result<T> {
+    fn aborted(err: JoinError) -> Self {
+        Err(Error::TaskAborted(err.to_string()).into())
+    }
+}
+
+/// reports a running task as done when dropped, even if the task panics
+struct DoneGuard<K> {
+    key: Option<K>,
+    done: mpsc::UnboundedSender<K>,
+}
+
+impl<K> Drop for DoneGuard<K> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            // the manager is gone if the server is shutting down
+            let _ = self.done.send(key);
+        }
+    }
+}
+
+enum Task<P, R> {
+    Queued(watch::Receiver<P>),
+    Running(JoinHandle<R>, watch::Receiver<P>),
+    /// results are kept around to be requested again later,
+    /// until they haven't been for `ttl`
+    Completed(Arc<R>, Instant),
+}
+
+struct QueuedTask<K, P, R> {
+    key: K,
+    priority: i32,
+    tx: watch::Sender<P>,
+    job: Job<P, R>,
+}
+
 pub struct TaskManager<K, P, R> {
-    tasks: HashMap<K, (JoinHandle<R>, watch::Receiver<P>)>,
+    tasks: HashMap<K, Task<P, R>>,
+    /// higher priority first, in submission order within the same priority
+    queue: VecDeque<QueuedTask<K, P, R>>,
+    running: usize,
+    max_running: usize,
+    ttl: Duration,
+    /// receives the key of every running task once it's done, see `task_done`
+    done: mpsc::UnboundedSender<K>,
 }
 
 impl<K, P, R> TaskManager<K, P, R>
 where
-    K: Eq + Hash,
+    K: Eq + Hash + Clone + Send + 'static,
     P: Send + Sync + 'static,
-    R: Send + 'static,
+    R: Outcome + Send + 'static,
 {
-    pub fn new() -> Self {
-        Self { tasks: HashMap::new() }
+    pub fn new(max_running: usize, ttl: Duration, done: mpsc::UnboundedSender<K>) -> Self {
+        Self {
+            tasks: HashMap::new(),
+            queue: VecDeque::new(),
+            running: 0,
+            max_running: max_running.max(1),
+            ttl,
+            done,
+        }
     }
 
-    pub fn submit<F>(&mut self, key: K, f: F) -> &mut (JoinHandle<R>, watch::Receiver<P>)
+    /// queues the task, it starts as soon as fewer than `max_running` tasks are running
+    pub fn submit<F>(&mut self, key: K, priority: i32, f: F)
     where
         F: FnOnce(watch::Sender<P>) -> R + Send + 'static,
         P: Default,
     {
-        self.tasks.entry(key).or_insert_with(|| {
-            let (tx, rx) = watch::channel(Default::default());
-            let join_handle = task::spawn_blocking(|| f(tx));
-            (join_handle, rx)
-        })
+        if self.tasks.contains_key(&key) {
+            return;
+        }
+
+        let (tx, rx) = watch::channel(Default::default());
+        self.tasks.insert(key.clone(), Task::Queued(rx));
+
+        let index = self
+            .queue
+            .iter()
+            .position(|queued| queued.priority < priority)
+            .unwrap_or(self.queue.len());
+        self.queue.insert(index, QueuedTask { key, priority, tx, job: Box::new(f) });
+
+        self.schedule();
+    }
+
+    /// must be called once for every notification sent through `done`,
+    /// returns the result of the task
+    pub async fn task_done(&mut self, key: &K) -> Option<Arc<R>> {
+        self.running -= 1;
+        self.schedule();
+
+        // the notification is sent as the job returns, the task is about to finish
+        let (key, task) = self.tasks.remove_entry(key)?;
+        let result = match task {
+            Task::Running(join_handle, _) => Arc::new(join_handle.await.unwrap_or_else(R::aborted)),
+            Task::Completed(result, _) => result,
+            Task::Queued(_) => unreachable!("queued tasks aren't running"),
+        };
+        self.tasks.insert(key, Task::Completed(result.clone(), Instant::now()));
+        Some(result)
+    }
+
+    fn schedule(&mut self) {
+        while self.running < self.max_running {
+            let Some(QueuedTask { key, tx, job, .. }) = self.queue.pop_front() else {
+                break;
+            };
+            let Some(Task::Queued(rx)) = self.tasks.remove(&key) else {
+                continue;
+            };
+
+            let guard = DoneGuard { key: Some(key.clone()), done: self.done.clone() };
+            let join_handle = task::spawn_blocking(move || {
+                let _guard = guard;
+                job(tx)
+            });
+            self.tasks.insert(key, Task::Running(join_handle, rx));
+            self.running += 1;
+        }
+    }
+
+    fn position(&self, key: &K) -> usize {
+        self.queue.iter().position(|queued| queued.key == *key).unwrap_or_default() + 1
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.tasks.contains_key(key)
     }
 
-    pub async fn poll(&mut self, key: &K) -> Option<TaskResponse<P, R>>
+    /// registers an already completed task, e.g. loaded from disk
+    pub fn complete(&mut self, key: K, result: R) {
+        self.tasks.insert(key, Task::Completed(Arc::new(result), Instant::now()));
+    }
+
+    /// forgets the results not requested for `ttl`
+    fn evict(&mut self) {
+        let ttl = self.ttl;
+        self.tasks.retain(|_, task| match task {
+            Task::Completed(_, accessed) => accessed.elapsed() < ttl,
+            _ => true,
+        });
+    }
+
+    pub async fn poll(&mut self, key: &K) -> Option<TaskResponse<P, Arc<R>>>
     where
         P: Copy
     {
-        let (key, (join_handle, mut rx)) = self.tasks.remove_entry(key)?;
-        Some(if rx.changed().await.is_err() {
+        self.evict();
+        let (key, task) = self.tasks.remove_entry(key)?;
+        let (join_handle, rx) = match task {
+            Task::Queued(rx) => {
+                self.tasks.insert(key.clone(), Task::Queued(rx));
+                return Some(TaskResponse::Queued(self.position(&key)));
+            }
+            Task::Running(join_handle, rx) => (join_handle, rx),
+            Task::Completed(result, _) => {
+                self.tasks.insert(key, Task::Completed(result.clone(), Instant::now()));
+                return Some(TaskResponse::Completed(result));
+            }
+        };
+
+        Some(if !join_handle.is_finished() {
             let progress = *rx.borrow();
             // still in progress: put handles back to tasks
-            self.tasks.insert(key, (join_handle, rx));
+            self.tasks.insert(key, Task::Running(join_handle, rx));
             TaskResponse::Pending(progress)
         } else {
-            let result = join_handle.await.unwrap();
+            let result = Arc::new(join_handle.await.unwrap_or_else(R::aborted));
+            self.tasks.insert(key, Task::Completed(result.clone(), Instant::now()));
             TaskResponse::Completed(result)
         })
     }
 
+    /// returns the result of a completed task, if any
+    pub async fn result(&mut self, key: &K) -> Option<Arc<R>>
+    where
+        P: Copy
+    {
+        match self.poll(key).await? {
+            TaskResponse::Queued(_) | TaskResponse::Pending(_) => None,
+            TaskResponse::Completed(result) => Some(result),
+        }
+    }
+
     pub fn progress(&self, key: &K) -> Option<watch::Receiver<P>> {
-        let (_, rx) = self.tasks.get(key)?;
-        Some(rx.clone())
+        match self.tasks.get(key)? {
+            Task::Queued(rx) | Task::Running(_, rx) => Some(rx.clone()),
+            Task::Completed(..) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Manager = TaskManager<u32, u32, eyre::Result<u32>>;
+
+    fn manager(max_running: usize, ttl: Duration) -> (Manager, mpsc::UnboundedReceiver<u32>) {
+        let (done, done_rx) = mpsc::unbounded_channel();
+        (TaskManager::new(max_running, ttl, done), done_rx)
+    }
+
+    fn value(result: Option<Arc<eyre::Result<u32>>>) -> Option<u32> {
+        result.map(|result| *result.as_ref().as_ref().unwrap())
+    }
+
+    #[tokio::test]
+    async fn keeps_recent_results() {
+        let (mut manager, _) = manager(1, Duration::from_secs(60));
+        manager.complete(1, Ok(10));
+        assert_eq!(value(manager.result(&1).await), Some(10));
+        assert_eq!(value(manager.result(&1).await), Some(10));
+    }
+
+    #[tokio::test]
+    async fn evicts_expired_results() {
+        let (mut manager, _) = manager(1, Duration::ZERO);
+        manager.complete(1, Ok(10));
+        assert!(manager.poll(&1).await.is_none());
+        assert!(!manager.contains(&1));
+    }
+
+    #[tokio::test]
+    async fn runs_by_priority_within_the_limit() {
+        let (mut manager, mut done) = manager(1, Duration::from_secs(60));
+        let (gate_tx, gate_rx) = std::sync::mpsc::channel::<()>();
+        manager.submit(1, 0, move |_| gate_rx.recv().map(|_| 1).map_err(eyre::Report::from));
+        manager.submit(2, 0, |_| Ok(2));
+        manager.submit(3, 5, |_| Ok(3));
+
+        assert!(matches!(manager.poll(&3).await, Some(TaskResponse::Queued(1))));
+        assert!(matches!(manager.poll(&2).await, Some(TaskResponse::Queued(2))));
+
+        gate_tx.send(()).unwrap();
+        assert_eq!(done.recv().await, Some(1));
+        assert_eq!(value(manager.task_done(&1).await), Some(1));
+        assert_eq!(done.recv().await, Some(3));
+    }
+
+    #[tokio::test]
+    async fn panicking_tasks_free_their_slot() {
+        let (mut manager, mut done) = manager(1, Duration::from_secs(60));
+        manager.submit(1, 0, |_| panic!("broken decoder"));
+        manager.submit(2, 0, |_| Ok(2));
+
+        assert_eq!(done.recv().await, Some(1));
+        let error = manager.task_done(&1).await.unwrap();
+        assert_eq!(done.recv().await, Some(2));
+        assert!(manager.result(&1).await.is_some());
+
+        let error = error.as_ref().as_ref().unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::TaskAborted(_))));
     }
 }
@@ -0,0 +1,350 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::analyzer::FileInfo;
+use crate::error::Error;
+use crate::export::format_time;
+use crate::remover::Remover;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Keep,
+    Trash,
+}
+
+/// a line of a deletion plan; exported results can be imported back as is,
+/// then the `keeper` column decides unless an `action` is given
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlanEntry {
+    path: PathBuf,
+    #[serde(default)]
+    action: Option<Action>,
+    #[serde(default)]
+    keeper: Option<bool>,
+    #[serde(default)]
+    group: Option<usize>,
+    /// expected size, the file must not have changed since the plan was made
+    #[serde(default)]
+    size: Option<u64>,
+    /// expected modification time, as exported
+    #[serde(default)]
+    modified: Option<String>,
+}
+
+/// a group of entries, as in the JSON export
+#[derive(Debug, Deserialize)]
+struct PlanGroup {
+    files: Vec<PlanEntry>,
+}
+
+impl PlanEntry {
+    fn action(&self) -> Option<Action> {
+        self.action.or(self.keeper.map(|keep| if keep { Action::Keep } else { Action::Trash }))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Conflict {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Trashed {
+    pub path: PathBuf,
+    pub id: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlanReport {
+    pub dry_run: bool,
+    /// the journal batch of the trashed files
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<Uuid>,
+    /// files to be trashed, or trashed unless it's a dry run
+    pub files: usize,
+    /// bytes to be freed
+    pub bytes: u64,
+    pub kept: usize,
+    /// entries skipped because the plan doesn't match the files anymore
+    pub conflicts: Vec<Conflict>,
+    pub trashed: Vec<Trashed>,
+    pub failed: Vec<Conflict>,
+}
+
+/// entries of an array of groups are numbered after their group unless they tell otherwise
+fn flatten(groups: Vec<PlanGroup>) -> Vec<PlanEntry> {
+    groups
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, group)| {
+            group.files.into_iter().map(move |entry| PlanEntry { group: entry.group.or(Some(i + 1)), ..entry })
+        })
+        .collect()
+}
+
+fn parse_json(data: &[u8]) -> serde_json::Result<Vec<PlanEntry>> {
+    let mut value: serde_json::Value = serde_json::from_slice(data)?;
+    // a whole export, the summary aside
+    if let Some(groups) = value.get_mut("groups") {
+        value = groups.take();
+    }
+
+    let grouped = value
+        .as_array()
+        .and_then(|items| items.first())
+        .is_some_and(|item| item.get("files").is_some());
+
+    if grouped {
+        Ok(flatten(serde_json::from_value(value)?))
+    } else {
+        serde_json::from_value(value)
+    }
+}
+
+/// plans are accepted as a JSON array of entries, as a JSON export
+/// or as CSV with a header line
+fn parse(data: &[u8]) -> Result<Vec<PlanEntry>> {
+    let json = data.iter().find(|b| !b.is_ascii_whitespace()).is_some_and(|b| [b'[', b'{'].contains(b));
+    let parsed = if json {
+        parse_json(data).map_err(|err| err.to_string())
+    } else {
+        csv::Reader::from_reader(data)
+            .deserialize()
+            .collect::<Result<Vec<PlanEntry>, _>>()
+            .map_err(|err| err.to_string())
+    };
+
+    let entries = parsed.map_err(|err| Error::BadRequest(format!("invalid deletion plan: {}", err)))?;
+    if entries.is_empty() {
+        return Err(Error::BadRequest("the deletion plan is empty".to_owned()).into());
+    }
+    Ok(entries)
+}
+
+/// returns the reason not to trash the file, if any
+fn check(entry: &PlanEntry) -> Result<FileInfo, String> {
+    let metadata = fs::symlink_metadata(&entry.path).map_err(|_| "the file is missing".to_owned())?;
+    let file = FileInfo::from_metadata(entry.path.clone(), &metadata).map_err(|err| err.to_string())?;
+
+    if entry.size.is_some_and(|size| size != file.size) {
+        return Err("the size has changed".to_owned());
+    }
+    if entry.modified.as_ref().is_some_and(|modified| *modified != format_time(file.modified)) {
+        return Err("the file was modified".to_owned());
+    }
+    Ok(file)
+}
+
+/// validates the plan against the files and, unless it's a dry run,
+/// trashes the files it marks, skipping the conflicting ones
+pub fn run(data: &[u8], remover: &Remover, dry_run: bool) -> Result<PlanReport> {
+    let entries = parse(data)?;
+    let mut report = PlanReport { dry_run, ..Default::default() };
+
+    // groups have to keep at least one file, unchanged since the plan was made
+    let mut kept_groups = HashSet::new();
+    for entry in &entries {
+        if let (Some(group), Some(Action::Keep)) = (entry.group, entry.action()) {
+            if check(entry).is_ok() {
+                kept_groups.insert(group);
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    let mut trash = Vec::new();
+    for entry in entries {
+        let conflict = |reason: &str| Conflict { path: entry.path.clone(), reason: reason.to_owned() };
+
+        if !seen.insert(entry.path.clone()) {
+            report.conflicts.push(conflict("listed more than once"));
+            continue;
+        }
+
+        match entry.action() {
+            None => report.conflicts.push(conflict("no action given")),
+            Some(Action::Keep) => report.kept += 1,
+            Some(Action::Trash) if entry.group.is_some_and(|g| !kept_groups.contains(&g)) => {
+                report.conflicts.push(conflict("no unchanged file of the group would be kept"));
+            }
+            Some(Action::Trash) => match check(&entry) {
+                Ok(file) => trash.push(file),
+                Err(reason) => report.conflicts.push(conflict(&reason)),
+            },
+        }
+    }
+
+    report.files = trash.len();
+    report.bytes = trash.iter().map(|file| file.size).sum();
+    if dry_run {
+        return Ok(report);
+    }
+
+    report.batch = Some(Uuid::new_v4());
+    for file in trash {
+        match remover.remove(&file.path, report.batch) {
+            Ok(id) => report.trashed.push(Trashed { path: file.path, id }),
+            Err(err) => {
+                tracing::error!(path = file.path.to_str(), "remove failed with: {:?}", err);
+                report.failed.push(Conflict { path: file.path, reason: err.to_string() });
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{Group, Groups, Member};
+    use crate::export::{self, Format};
+    use std::path::Path;
+
+    struct Library {
+        dir: tempfile::TempDir,
+        remover: Remover,
+    }
+
+    impl Library {
+        fn new(files: &[&str]) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+            fs::create_dir(dir.path().join("trash")).unwrap();
+            for name in files {
+                fs::write(dir.path().join(name), name).unwrap();
+            }
+            let remover = Remover::new(dir.path().join("trash"));
+            Self { dir, remover }
+        }
+
+        fn path(&self, name: &str) -> PathBuf {
+            self.dir.path().join(name)
+        }
+
+        fn groups(&self, groups: &[&[&str]]) -> Groups {
+            groups
+                .iter()
+                .map(|names| {
+                    let files = names
+                        .iter()
+                        .map(|name| Member { file: FileInfo::from_path(&self.path(name)).unwrap(), score: None, dist: None })
+                        .collect();
+                    Group { files, status: None }
+                })
+                .collect()
+        }
+
+        fn csv(&self, rows: &[(&str, usize, bool)]) -> Vec<u8> {
+            let mut csv = "group,path,size,keeper\n".to_owned();
+            for (name, group, keeper) in rows {
+                csv += &format!("{},{},{},{}\n", group, self.path(name).display(), name.len(), keeper);
+            }
+            csv.into_bytes()
+        }
+    }
+
+    fn reasons(report: &PlanReport) -> Vec<(&Path, &str)> {
+        report.conflicts.iter().map(|c| (c.path.file_name().unwrap().as_ref(), c.reason.as_str())).collect()
+    }
+
+    #[test]
+    fn dry_runs_leave_files_in_place() {
+        let library = Library::new(&["a.png", "b.png"]);
+        let plan = library.csv(&[("a.png", 1, true), ("b.png", 1, false)]);
+
+        let report = run(&plan, &library.remover, true).unwrap();
+        assert_eq!((report.files, report.bytes, report.kept), (1, 5, 1));
+        assert!(report.conflicts.is_empty() && report.trashed.is_empty());
+        assert!(library.path("b.png").exists());
+
+        let report = run(&plan, &library.remover, false).unwrap();
+        assert_eq!(report.trashed.len(), 1);
+        assert!(library.path("a.png").exists() && !library.path("b.png").exists());
+    }
+
+    #[test]
+    fn rejects_changed_and_duplicate_entries() {
+        let library = Library::new(&["a.png", "b.png", "c.png"]);
+        let mut plan = library.csv(&[("a.png", 1, true), ("b.png", 1, false), ("b.png", 1, false)]);
+        plan.extend(format!("1,{},1,false\n", library.path("c.png").display()).bytes());
+
+        let report = run(&plan, &library.remover, true).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(reasons(&report), [
+            (Path::new("b.png"), "listed more than once"),
+            (Path::new("c.png"), "the size has changed"),
+        ]);
+    }
+
+    #[test]
+    fn groups_keep_an_unchanged_file() {
+        let library = Library::new(&["a.png", "b.png", "c.png", "d.png"]);
+        let plan = library.csv(&[("a.png", 1, true), ("b.png", 1, false), ("c.png", 2, false), ("d.png", 2, true)]);
+        // the keeper of the first group changed since the plan was made
+        fs::write(library.path("a.png"), "changed").unwrap();
+
+        let report = run(&plan, &library.remover, true).unwrap();
+        assert_eq!(report.files, 1);
+        assert_eq!(reasons(&report), [(Path::new("b.png"), "no unchanged file of the group would be kept")]);
+    }
+
+    /// the largest file of each group is kept
+    fn round_trip(format: Format) {
+        let library = Library::new(&["keeper1.png", "a.png", "keeper2.png", "b.png", "c.png"]);
+        let groups = library.groups(&[&["a.png", "keeper1.png"], &["b.png", "keeper2.png", "c.png"]]);
+        let exported = export::export(&groups, format, "plan").unwrap();
+
+        let report = run(&exported, &library.remover, false).unwrap();
+        assert!(report.conflicts.is_empty(), "{:?}", report.conflicts);
+        assert_eq!((report.files, report.kept), (3, 2));
+        let remaining: Vec<_> = ["keeper1.png", "a.png", "keeper2.png", "b.png", "c.png"]
+            .into_iter()
+            .filter(|name| library.path(name).exists())
+            .collect();
+        assert_eq!(remaining, ["keeper1.png", "keeper2.png"]);
+    }
+
+    #[test]
+    fn imports_exported_csv() {
+        round_trip(Format::Csv);
+    }
+
+    #[test]
+    fn imports_exported_json() {
+        round_trip(Format::Json);
+    }
+
+    #[test]
+    fn accepts_grouped_json() {
+        let library = Library::new(&["a.png", "b.png"]);
+        let plan = serde_json::json!([
+            { "files": [
+                { "path": library.path("a.png"), "keeper": true },
+                { "path": library.path("b.png"), "keeper": false },
+            ] },
+        ]);
+
+        let entries = parse(&serde_json::to_vec(&plan).unwrap()).unwrap();
+        assert!(entries.iter().all(|entry| entry.group == Some(1)));
+        let report = run(&serde_json::to_vec(&plan).unwrap(), &library.remover, true).unwrap();
+        assert_eq!((report.files, report.kept), (1, 1));
+    }
+
+    #[test]
+    fn rejects_empty_and_malformed_plans() {
+        let library = Library::new(&[]);
+        for plan in ["[]", "[{\"action\": \"trash\"}]", "path,action\n"] {
+            let err = run(plan.as_bytes(), &library.remover, true).unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::BadRequest(_))), "{}", plan);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+use image::DynamicImage;
+use image_hasher::{Hasher, ImageHash};
+
+/// relative sizes of the hashed regions, the whole image comes first;
+/// a geometric progression keeps the centers of a crop aligned with the original
+const SCALES: [f32; 3] = [1.0, 0.8, 0.64];
+
+/// relative positions of the regions along each axis,
+/// as a fraction of the space left after placing a region
+const OFFSETS: [f32; 3] = [0.0, 0.5, 1.0];
+
+/// hashes overlapping regions of the image at several scales,
+/// so that a crop or a letterbox of the original still has
+/// some regions in common with it
+pub fn hash_regions(hasher: &Hasher, image: &DynamicImage) -> Vec<ImageHash> {
+    let (width, height) = (image.width(), image.height());
+    let mut hashes = Vec::new();
+
+    for scale in SCALES {
+        let w = ((width as f32 * scale) as u32).max(1);
+        let h = ((height as f32 * scale) as u32).max(1);
+
+        for oy in OFFSETS {
+            for ox in OFFSETS {
+                let x = ((width - w) as f32 * ox) as u32;
+                let y = ((height - h) as f32 * oy) as u32;
+                let region = image.crop_imm(x, y, w, h);
+                hashes.push(hasher.hash_image(&region));
+
+                if w == width {
+                    break;
+                }
+            }
+
+            if h == height {
+                break;
+            }
+        }
+    }
+
+    hashes
+}
+
+fn matching(a: &[ImageHash], b: &[ImageHash], max_dist: u32) -> usize {
+    a
+        .iter()
+        .filter(|ha| b.iter().any(|hb| ha.dist(hb) <= max_dist))
+        .count()
+}
+
+/// returns the number of regions both images have in common: regions of
+/// either image having a close enough counterpart in the other one, counted
+/// on the side with the fewest matches so that the order of `a` and `b` is irrelevant
+pub fn shared_regions(a: &[ImageHash], b: &[ImageHash], max_dist: u32) -> usize {
+    matching(a, b, max_dist).min(matching(b, a, max_dist))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(byte: u8) -> ImageHash {
+        ImageHash::from_bytes(&[byte; 8]).unwrap()
+    }
+
+    #[test]
+    fn shared_regions_are_symmetric() {
+        // every region of `a` matches the single region of `b`
+        let a = [hash(0), hash(0), hash(0)];
+        let b = [hash(0), hash(0xff)];
+        assert_eq!(shared_regions(&a, &b, 0), 1);
+        assert_eq!(shared_regions(&b, &a, 0), 1);
+    }
+
+    #[test]
+    fn distant_regions_are_not_shared() {
+        assert_eq!(shared_regions(&[hash(0)], &[hash(0xff)], 10), 0);
+        assert_eq!(shared_regions(&[hash(0)], &[hash(1)], 8), 1);
+    }
+}
@@ -1,12 +1,204 @@
-use eyre::Result;
-use serde::{Serialize, de::DeserializeOwned};
-use std::{path::{PathBuf, Path}, fs};
+use eyre::{eyre, Result};
+use image::io::Reader;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeMap, HashMap}, path::{PathBuf, Path}, fs, sync::Mutex, time::SystemTime};
 use uuid::Uuid;
 
-#[derive(Debug, Serialize)]
+use crate::error::{Error, ErrorBody};
+use crate::journal::{self, Journal, JournalEntry, JournalLock, Operation};
+use crate::linker;
+use crate::store::now;
+
+/// what the trash remembers about a removed file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Meta {
+    /// the original location
+    path: PathBuf,
+    /// deletion time in milliseconds since the epoch
+    removed: u64,
+    size: u64,
+    /// the journal batch of the removal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    batch: Option<Uuid>,
+    /// the content type, the trashed file doesn't keep its extension
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    /// the file whose link took the place of this one, see `linker`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    keeper: Option<PathBuf>,
+}
+
+/// entries written before the deletion time and size were kept hold only the path
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMeta {
+    Meta(Meta),
+    Legacy(PathBuf),
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct RemovedFile {
     id: String,
+    #[serde(flatten)]
+    meta: Meta,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    #[default]
+    Removed,
+    Path,
+    Size,
+}
+
+/// selects trash entries, all of them when empty
+#[derive(Debug, Default, Deserialize)]
+pub struct TrashFilter {
+    /// only files removed from this folder or its subfolders
+    folder: Option<PathBuf>,
+    batch: Option<Uuid>,
+    /// deletion time range in milliseconds since the epoch, inclusive
+    from: Option<u64>,
+    to: Option<u64>,
+}
+
+impl TrashFilter {
+    fn is_empty(&self) -> bool {
+        self.folder.is_none() && self.batch.is_none() && self.from.is_none() && self.to.is_none()
+    }
+
+    fn matches(&self, meta: &Meta) -> bool {
+        self.folder.as_ref().is_none_or(|folder| meta.path.starts_with(folder))
+            && self.batch.is_none_or(|batch| meta.batch == Some(batch))
+            && self.from.is_none_or(|from| meta.removed >= from)
+            && self.to.is_none_or(|to| meta.removed <= to)
+    }
+}
+
+/// a page of the trash listing, the latest removed files first by default;
+/// paths and sizes are sorted ascending unless asked otherwise.
+/// Query strings can't be flattened, the filter is extracted next to it
+#[derive(Debug, Default, Deserialize)]
+pub struct TrashQuery {
+    #[serde(default)]
+    sort: SortBy,
+    asc: Option<bool>,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+/// the files to restore, either by id or by filter
+#[derive(Debug, Default, Deserialize)]
+pub struct RestoreRequest {
+    #[serde(default)]
+    ids: Vec<String>,
+    #[serde(flatten)]
+    filter: TrashFilter,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Restored {
+    id: String,
+    /// where the file was restored to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreReport {
+    /// the journal batch of the restored files
+    batch: Uuid,
+    restored: usize,
+    failed: usize,
+    /// the number of failures by error code
+    errors: BTreeMap<&'static str, usize>,
+    files: Vec<Restored>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Removed {
     path: PathBuf,
+    /// the trash id of the removed file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ErrorBody>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RemoveReport {
+    /// the journal batch of the removed files, undoing it restores them
+    batch: Uuid,
+    removed: usize,
+    failed: usize,
+    /// the number of failures by error code
+    errors: BTreeMap<&'static str, usize>,
+    files: Vec<Removed>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrashPage {
+    /// files matching the filter
+    total: usize,
+    files: Vec<RemovedFile>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum IssueKind {
+    /// data without metadata, the original location is unknown
+    OrphanData,
+    /// metadata that can't be read
+    CorruptMeta,
+    /// metadata of a file that isn't there
+    MissingData,
+    /// an operation didn't complete, see `Remover::pending_path`
+    Interrupted,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Issue {
+    id: String,
+    kind: IssueKind,
+    /// the original location, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<PathBuf>,
+    /// what was done about the issue
+    #[serde(skip_serializing_if = "Option::is_none")]
+    repaired: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    entry: JournalEntry,
+    undone: bool,
+}
+
+/// operations to undo or redo
+#[derive(Debug, Clone, Copy)]
+pub enum Selection {
+    /// the most recent ones
+    Last(usize),
+    Batch(Uuid),
+}
+
+/// the outcome of undoing or redoing a single operation
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reverted {
+    seq: u64,
+    operation: Operation,
+    id: String,
+    path: PathBuf,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
 }
 
 /// "removes" files by placing them into a designated directory
@@ -15,6 +207,20 @@ pub struct RemovedFile {
 #[derive(Debug)]
 pub struct Remover {
     root: PathBuf,
+    journal: Journal,
+    /// the trash entries by id, kept in sync by the operations
+    index: Mutex<HashMap<String, RemovedFile>>,
+}
+
+fn guess_mime(path: &Path) -> Option<String> {
+    mime_guess::from_path(path).first_raw().map(str::to_owned)
+}
+
+fn log_append(result: Result<()>) {
+    // the operation is done already, failing it now would only confuse
+    if let Err(err) = result {
+        tracing::error!("unable to record the operation in the journal: {:?}", err);
+    }
 }
 
 impl Remover {
@@ -22,7 +228,14 @@ impl Remover {
     where
         PathBuf: From<T>
     {
-        Self { root: PathBuf::from(root) }
+        let root = PathBuf::from(root);
+        let journal = Journal::new(root.join("journal.jsonl"));
+        let remover = Self { root, journal, index: Default::default() };
+        match remover.scan() {
+            Ok(index) => *remover.index.lock().unwrap() = index,
+            Err(err) => tracing::error!("unable to read the trash: {:?}", err),
+        }
+        remover
     }
 
     fn meta_path(&self, id: &str) -> PathBuf {
@@ -33,88 +246,624 @@ impl Remover {
         self.root.join(id).with_extension("dat")
     }
 
-    fn read_meta<T: DeserializeOwned>(&self, id: &str) -> Result<T> {
-        let path = self.meta_path(id);
+    /// metadata of an entry being added or taken out of the trash, an operation
+    /// interrupted by a crash leaves it behind, see `check`
+    fn pending_path(&self, id: &str) -> PathBuf {
+        self.root.join(id).with_extension("tmp")
+    }
+
+    fn read_meta(&self, id: &str, path: &Path) -> Result<Meta> {
         let content = fs::read(path)?;
-        let meta = serde_json::from_slice(&content)?;
-        Ok(meta)
+        match serde_json::from_slice(&content)? {
+            StoredMeta::Meta(meta) => Ok(meta),
+            StoredMeta::Legacy(origin) => {
+                // the metadata was written when the file was removed
+                let removed = fs::metadata(path)?.modified()?.duration_since(SystemTime::UNIX_EPOCH)?;
+                let size = fs::metadata(self.data_path(id)).map_or(0, |metadata| metadata.len());
+                let mime = guess_mime(&origin);
+                Ok(Meta { path: origin, removed: removed.as_millis() as u64, size, batch: None, mime, keeper: None })
+            }
+        }
     }
 
-    fn write_meta<T: Serialize + ?Sized>(&self, id: &str, meta: &T) -> Result<()> {
-        let path = self.meta_path(id);
+    fn write_meta(&self, path: &Path, meta: &Meta) -> Result<()> {
         let content = serde_json::to_string(meta)?;
         fs::write(path, content)?;
         Ok(())
     }
 
-    fn remove_meta(&self, id: &str) -> Result<()> {
-        let path = self.meta_path(id);
-        fs::remove_file(path)?;
-        Ok(())
-    }
-
     fn read_entry(&self, path: PathBuf) -> Option<RemovedFile> {
         let id = path.file_stem().and_then(|s| s.to_str())?;
         let ext = path.extension()?;
         if ext == "json" {
-            // read the original file path
-            let path = self.read_meta(id).ok()?;
-            Some(RemovedFile {
-                id: id.to_owned(),
-                path,
-            })
+            match self.read_meta(id, &path) {
+                Ok(meta) => Some(RemovedFile {
+                    id: id.to_owned(),
+                    meta,
+                }),
+                Err(err) => {
+                    tracing::warn!(id, "skipping corrupt trash entry: {:?}", err);
+                    None
+                }
+            }
         } else {
             None
         }
     }
 
-    pub fn resolve(&self, id: &str) -> Result<PathBuf> {
-        // we could have some checks here to make sure file exists
-        Ok(self.data_path(id))
+    /// the metadata of a trash entry; ids come from clients and only
+    /// the indexed ones are turned into paths, `../` can't escape the trash
+    fn entry(&self, id: &str) -> Result<Meta> {
+        match self.index.lock().unwrap().get(id) {
+            Some(file) => Ok(file.meta.clone()),
+            None => Err(Error::TrashEntryNotFound(id.to_owned()).into()),
+        }
+    }
+
+    /// the data of a removed file and its content type
+    pub fn open(&self, id: &str) -> Result<(PathBuf, Mime)> {
+        let meta = self.entry(id)?;
+        let path = self.resolve(id)?;
+        let mime = meta
+            .mime
+            .or_else(|| guess_mime(&meta.path))
+            .and_then(|mime| mime.parse().ok())
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        Ok((path, mime))
     }
 
-    pub fn remove(&self, path: &Path) -> Result<String> {
-        let id = Uuid::new_v4().to_string();
-        self.write_meta(&id, path)?;
+    fn resolve(&self, id: &str) -> Result<PathBuf> {
+        let path = self.data_path(id);
+        if !path.is_file() {
+            return Err(Error::TrashEntryNotFound(id.to_owned()).into());
+        }
+        Ok(path)
+    }
+
+    fn move_to_trash(&self, path: &Path, id: &str, batch: Option<Uuid>, keeper: Option<&Path>) -> Result<()> {
+        let metadata = fs::symlink_metadata(path).map_err(|err| Error::io(path, err))?;
+        if !metadata.is_file() {
+            return Err(Error::FileNotFound(path.to_owned()).into());
+        }
+        if self.meta_path(id).exists() || self.data_path(id).exists() {
+            return Err(eyre!("the trash already holds a file with id {}", id));
+        }
+
+        // the metadata is pending until the file is moved
+        let pending = self.pending_path(id);
+        let meta = Meta {
+            path: path.to_owned(),
+            removed: now(),
+            size: metadata.len(),
+            batch,
+            mime: guess_mime(path),
+            keeper: keeper.map(Path::to_owned),
+        };
+        self.write_meta(&pending, &meta)?;
 
-        // move the file
-        let dest = self.data_path(&id);
+        let dest = self.data_path(id);
         tracing::info!(src = path.to_str(), dest = dest.to_str(), "moving file");
-        fs::rename(path, dest)?;
-        Ok(id)
+        if let Err(err) = fs::rename(path, dest) {
+            // don't leave an entry without data behind
+            fs::remove_file(pending)?;
+            return Err(Error::io(path, err).into());
+        }
+
+        fs::rename(pending, self.meta_path(id))?;
+        self.index.lock().unwrap().insert(id.to_owned(), RemovedFile { id: id.to_owned(), meta });
+        Ok(())
     }
 
-    pub fn restore(&self, id: &str) -> Result<PathBuf> {
-        let dest: PathBuf = self.read_meta(id)?;
+    fn move_back(&self, id: &str) -> Result<PathBuf> {
+        let meta = self.entry(id)?;
+        let dest = meta.path;
         let src = self.data_path(id);
+        if !src.is_file() {
+            let err = eyre!("the data of the removed file is missing");
+            return Err(Error::CorruptTrashEntry(id.to_owned(), err).into());
+        }
+        // renaming replaces whatever took the place of the file,
+        // only the link made by the linker may be replaced
+        if fs::symlink_metadata(&dest).is_ok() {
+            let linked = meta.keeper.is_some_and(|keeper| linker::links_to(&keeper, &dest).unwrap_or(false));
+            if !linked {
+                return Err(Error::RestoreConflict(id.to_owned(), dest).into());
+            }
+        }
+
+        // the metadata is pending until the file is moved
+        let pending = self.pending_path(id);
+        fs::rename(self.meta_path(id), &pending)?;
+
         tracing::info!(src = src.to_str(), dest = dest.to_str(), "moving file");
-        fs::rename(src, &dest)?;
-        self.remove_meta(id)?;
+        if let Err(err) = fs::rename(src, &dest) {
+            fs::rename(pending, self.meta_path(id))?;
+            return Err(Error::io(&dest, err).into());
+        }
+
+        fs::remove_file(pending)?;
+        self.index.lock().unwrap().remove(id);
         Ok(dest)
     }
 
-    pub fn list_removed(&self) -> Result<Vec<RemovedFile>> {
-        let mut files = Vec::new();
+    pub fn remove(&self, path: &Path, batch: Option<Uuid>) -> Result<String> {
+        self.trash(path, batch, None)
+    }
+
+    /// removes a copy about to be replaced with a link to the keeper,
+    /// restoring the copy replaces the link again
+    pub fn remove_linked(&self, path: &Path, keeper: &Path, batch: Option<Uuid>) -> Result<String> {
+        self.trash(path, batch, Some(keeper))
+    }
+
+    fn trash(&self, path: &Path, batch: Option<Uuid>, keeper: Option<&Path>) -> Result<String> {
+        let mut journal = self.journal.lock();
+        let id = Uuid::new_v4().to_string();
+        self.move_to_trash(path, &id, batch, keeper)?;
+        log_append(journal.append(Operation::Remove, &id, path.to_owned(), batch, (None, None)));
+        Ok(id)
+    }
+
+    pub fn restore(&self, id: &str, batch: Option<Uuid>) -> Result<PathBuf> {
+        let mut journal = self.journal.lock();
+        let path = self.move_back(id)?;
+        log_append(journal.append(Operation::Restore, id, path.clone(), batch, (None, None)));
+        Ok(path)
+    }
+
+    /// deletes the removed file for good
+    pub fn purge(&self, id: &str, batch: Option<Uuid>) -> Result<()> {
+        let mut journal = self.journal.lock();
+        let origin = self.entry(id)?.path;
+        let path = self.resolve(id)?;
+
+        // the metadata is pending until the file is deleted
+        let pending = self.pending_path(id);
+        fs::rename(self.meta_path(id), &pending)?;
+
+        tracing::info!(path = path.to_str(), "deleting file");
+        if let Err(err) = fs::remove_file(path) {
+            fs::rename(pending, self.meta_path(id))?;
+            return Err(err.into());
+        }
+
+        fs::remove_file(pending)?;
+        self.index.lock().unwrap().remove(id);
+        log_append(journal.append(Operation::Purge, id, origin, batch, (None, None)));
+        Ok(())
+    }
+
+    /// finds trash entries left inconsistent by crashes or manual changes and,
+    /// if asked to, repairs them; nothing is deleted but metadata of missing files
+    pub fn check(&self, repair: bool) -> Result<Vec<Issue>> {
+        // no operation runs meanwhile
+        let _journal = self.journal.lock();
+
+        // which of metadata, data and pending metadata each id has
+        let mut entries: BTreeMap<String, (bool, bool, bool)> = BTreeMap::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let (Some(id), Some(ext)) = (path.file_stem().and_then(|s| s.to_str()), path.extension()) else {
+                continue;
+            };
+            let files = entries.entry(id.to_owned()).or_default();
+            match ext.to_str() {
+                Some("json") => files.0 = true,
+                Some("dat") => files.1 = true,
+                Some("tmp") => files.2 = true,
+                _ => {}
+            }
+        }
+
+        let mut issues = Vec::new();
+        for (id, (meta, data, pending)) in entries {
+            let kind = if pending {
+                IssueKind::Interrupted
+            } else if meta && !data {
+                IssueKind::MissingData
+            } else if meta {
+                match self.read_meta(&id, &self.meta_path(&id)) {
+                    Ok(_) => continue,
+                    Err(_) => IssueKind::CorruptMeta,
+                }
+            } else if data {
+                IssueKind::OrphanData
+            } else {
+                continue;
+            };
+
+            let path = if pending { self.pending_path(&id) } else { self.meta_path(&id) };
+            let path = self.read_meta(&id, &path).ok().map(|meta| meta.path);
+            let repaired = if repair {
+                match self.repair(&id, kind, meta, data) {
+                    Ok(action) => Some(action.to_owned()),
+                    Err(err) => {
+                        tracing::error!(id, "repair failed with: {:?}", err);
+                        Some(format!("repair failed: {}", err))
+                    }
+                }
+            } else {
+                None
+            };
+
+            tracing::warn!(id, "trash entry issue {:?}", kind);
+            issues.push(Issue { id, kind, path, repaired });
+        }
+
+        if repair {
+            *self.index.lock().unwrap() = self.scan()?;
+        }
+        Ok(issues)
+    }
+
+    /// the file data is kept, with a placeholder path if the original one is unknown
+    fn adopt(&self, id: &str) -> Result<()> {
+        let recovered = self.root.join("recovered");
+        fs::create_dir_all(&recovered)?;
+        let path = fs::canonicalize(recovered)?.join(id);
+        let data = self.data_path(id);
+        let size = fs::metadata(&data)?.len();
+        // the content type is told by the content
+        let format = Reader::open(&data)?.with_guessed_format()?.format();
+        let mime = format.map(|format| format.to_mime_type().to_owned());
+        self.write_meta(&self.meta_path(id), &Meta { path, removed: now(), size, batch: None, mime, keeper: None })
+    }
+
+    fn repair(&self, id: &str, kind: IssueKind, meta: bool, data: bool) -> Result<&'static str> {
+        let action = match kind {
+            // the file was moved in: keep it in the trash
+            IssueKind::Interrupted if data && !meta => {
+                fs::rename(self.pending_path(id), self.meta_path(id))?;
+                "kept in the trash"
+            }
+            // the file was never moved in, or was moved out already
+            IssueKind::Interrupted => {
+                fs::remove_file(self.pending_path(id))?;
+                "dropped the pending metadata"
+            }
+            IssueKind::MissingData => {
+                fs::remove_file(self.meta_path(id))?;
+                "dropped the metadata"
+            }
+            IssueKind::CorruptMeta if !data => {
+                fs::remove_file(self.meta_path(id))?;
+                "dropped the metadata"
+            }
+            IssueKind::CorruptMeta | IssueKind::OrphanData => {
+                self.adopt(id)?;
+                "adopted with a placeholder path"
+            }
+        };
+
+        Ok(action)
+    }
+
+    /// recent operations first
+    pub fn history(&self, limit: Option<usize>) -> Result<Vec<HistoryEntry>> {
+        let entries = self.journal.lock().entries()?;
+        let undone = journal::undone(&entries);
+        let history = entries
+            .into_iter()
+            .rev()
+            .take(limit.unwrap_or(usize::MAX))
+            .map(|entry| HistoryEntry { undone: undone.contains_key(&entry.seq), entry })
+            .collect();
+        Ok(history)
+    }
+
+    /// makes the operation again (`undo` is false) or the reverse one,
+    /// reusing the trash id, so that the history can be replayed in either direction
+    fn revert(&self, journal: &mut JournalLock, entry: &JournalEntry, undo: bool, batch: Uuid) -> Reverted {
+        let forward = match entry.operation {
+            Operation::Remove => true,
+            Operation::Restore => false,
+            Operation::Purge => {
+                let error = Some("purged files can't be brought back".to_owned());
+                return Reverted { seq: entry.seq, operation: entry.operation, id: entry.id.clone(), path: entry.path.clone(), error };
+            }
+        };
+
+        let result = if forward != undo {
+            self.move_to_trash(&entry.path, &entry.id, Some(batch), None).map(|_| Operation::Remove)
+        } else {
+            self.move_back(&entry.id).map(|_| Operation::Restore)
+        };
+
+        let error = match result {
+            Ok(operation) => {
+                let reverts = if undo { (Some(entry.seq), None) } else { (None, Some(entry.seq)) };
+                log_append(journal.append(operation, &entry.id, entry.path.clone(), Some(batch), reverts));
+                None
+            }
+            Err(err) => {
+                tracing::error!(id = entry.id, "revert failed with: {:?}", err);
+                Some(err.to_string())
+            }
+        };
+
+        Reverted { seq: entry.seq, operation: entry.operation, id: entry.id.clone(), path: entry.path.clone(), error }
+    }
+
+    fn revert_all(&self, selection: Selection, undo: bool) -> Result<Vec<Reverted>> {
+        let mut journal = self.journal.lock();
+        let entries = journal.entries()?;
+        let undone = journal::undone(&entries);
+
+        // undo the latest operations first, redo the latest undone ones first
+        let mut candidates: Vec<_> = entries
+            .iter()
+            .filter(|e| e.is_original() && undone.contains_key(&e.seq) != undo)
+            .collect();
+        if undo {
+            candidates.reverse();
+        } else {
+            candidates.sort_by_key(|e| std::cmp::Reverse(undone[&e.seq]));
+        }
+
+        let selected: Vec<_> = match selection {
+            // purges can't be reverted, they don't count as the last operations
+            Selection::Last(count) => candidates
+                .into_iter()
+                .filter(|e| e.operation != Operation::Purge)
+                .take(count)
+                .collect(),
+            Selection::Batch(batch) => candidates.into_iter().filter(|e| e.batch == Some(batch)).collect(),
+        };
+
+        let batch = Uuid::new_v4();
+        let reverted = selected
+            .into_iter()
+            .map(|entry| self.revert(&mut journal, entry, undo, batch))
+            .collect();
+        Ok(reverted)
+    }
+
+    pub fn undo(&self, selection: Selection) -> Result<Vec<Reverted>> {
+        self.revert_all(selection, true)
+    }
+
+    pub fn redo(&self, selection: Selection) -> Result<Vec<Reverted>> {
+        self.revert_all(selection, false)
+    }
+
+    fn scan(&self) -> Result<HashMap<String, RemovedFile>> {
+        let mut files = HashMap::new();
 
         for entry in fs::read_dir(&self.root)? {
             let entry = entry?;
             let path = entry.path();
             if let Some(file) = self.read_entry(path) {
-                files.push(file);
+                files.insert(file.id.clone(), file);
             }
         }
 
         Ok(files)
     }
 
-    pub fn restore_all(&self) -> Result<()> {
-        let files = self.list_removed()?;
-        for file in files {
-            if let Err(err) = self.restore(&file.id) {
-                tracing::error!(id = file.id, "restore failed with: {:?}", err);
+    pub fn list_removed(&self, filter: &TrashFilter, query: &TrashQuery) -> TrashPage {
+        let mut files: Vec<_> = self
+            .index
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|file| filter.matches(&file.meta))
+            .cloned()
+            .collect();
+
+        match query.sort {
+            SortBy::Removed => files.sort_by_key(|file| file.meta.removed),
+            SortBy::Path => files.sort_by(|a, b| a.meta.path.cmp(&b.meta.path)),
+            SortBy::Size => files.sort_by_key(|file| file.meta.size),
+        }
+        let asc = query.asc.unwrap_or(!matches!(query.sort, SortBy::Removed));
+        if !asc {
+            files.reverse();
+        }
+
+        let total = files.len();
+        let files = files
+            .into_iter()
+            .skip(query.offset)
+            .take(query.limit.unwrap_or(usize::MAX))
+            .collect();
+        TrashPage { total, files }
+    }
+
+    /// removes the files as a single batch
+    pub fn remove_all(&self, paths: Vec<PathBuf>) -> RemoveReport {
+        let batch = Uuid::new_v4();
+        let mut report = RemoveReport { batch, removed: 0, failed: 0, errors: BTreeMap::new(), files: Vec::new() };
+
+        for path in paths {
+            match self.remove(&path, Some(batch)) {
+                Ok(id) => {
+                    report.removed += 1;
+                    report.files.push(Removed { path, id: Some(id), error: None });
+                }
+                Err(err) => {
+                    tracing::error!(path = path.to_str(), "remove failed with: {:?}", err);
+                    let (_, error) = ErrorBody::new(&err);
+                    report.failed += 1;
+                    *report.errors.entry(error.code()).or_default() += 1;
+                    report.files.push(Removed { path, id: None, error: Some(error) });
+                }
             }
         }
 
-        Ok(())
+        report
+    }
+
+    fn restore_ids(&self, ids: Vec<String>) -> RestoreReport {
+        let batch = Uuid::new_v4();
+        let mut report = RestoreReport { batch, restored: 0, failed: 0, errors: BTreeMap::new(), files: Vec::new() };
+
+        for id in ids {
+            match self.restore(&id, Some(batch)) {
+                Ok(path) => {
+                    report.restored += 1;
+                    report.files.push(Restored { id, path: Some(path), error: None });
+                }
+                Err(err) => {
+                    tracing::error!(id, "restore failed with: {:?}", err);
+                    let (_, error) = ErrorBody::new(&err);
+                    report.failed += 1;
+                    *report.errors.entry(error.code()).or_default() += 1;
+                    report.files.push(Restored { id, path: None, error: Some(error) });
+                }
+            }
+        }
+
+        report
+    }
+
+    /// restores the files given by id or, if none is, the ones matching the filter
+    pub fn restore_selected(&self, req: RestoreRequest) -> Result<RestoreReport> {
+        if req.ids.is_empty() && req.filter.is_empty() {
+            return Err(Error::BadRequest("no files selected to restore".to_owned()).into());
+        }
+
+        let ids = if req.ids.is_empty() {
+            let index = self.index.lock().unwrap();
+            index.values().filter(|file| req.filter.matches(&file.meta)).map(|file| file.id.clone()).collect()
+        } else {
+            req.ids
+        };
+        Ok(self.restore_ids(ids))
+    }
+
+    pub fn restore_all(&self) -> RestoreReport {
+        let ids = self.index.lock().unwrap().keys().cloned().collect();
+        self.restore_ids(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    struct Trash {
+        dir: TempDir,
+        remover: Remover,
+    }
+
+    impl Trash {
+        fn new() -> Self {
+            let dir = TempDir::new().unwrap();
+            fs::create_dir(dir.path().join("trash")).unwrap();
+            let remover = Remover::new(dir.path().join("trash"));
+            Self { dir, remover }
+        }
+
+        /// creates a file in the library and removes it
+        fn remove(&self, name: &str) -> (PathBuf, String) {
+            let path = self.dir.path().join(name);
+            fs::write(&path, name).unwrap();
+            let id = self.remover.remove(&path, None).unwrap();
+            (path, id)
+        }
+    }
+
+    #[test]
+    fn lists_paths_ascending_by_default() {
+        let trash = Trash::new();
+        let (a, _) = trash.remove("a.png");
+        let (b, _) = trash.remove("b.png");
+        let paths = |sort, asc| {
+            let query = TrashQuery { sort, asc, ..Default::default() };
+            trash.remover.list_removed(&TrashFilter::default(), &query).files.into_iter().map(|file| file.meta.path).collect::<Vec<_>>()
+        };
+
+        assert_eq!(paths(SortBy::Path, None), vec![a.clone(), b.clone()]);
+        assert_eq!(paths(SortBy::Path, Some(false)), vec![b.clone(), a.clone()]);
+    }
+
+    #[test]
+    fn repairs_inconsistent_entries() {
+        let trash = Trash::new();
+        let (_, missing) = trash.remove("a.png");
+        fs::remove_file(trash.remover.data_path(&missing)).unwrap();
+        let (b, interrupted) = trash.remove("b.png");
+        fs::rename(trash.remover.meta_path(&interrupted), trash.remover.pending_path(&interrupted)).unwrap();
+        fs::write(trash.remover.data_path("orphan"), "data").unwrap();
+
+        let issues = trash.remover.check(true).unwrap();
+        let kind = |id: &str| issues.iter().find(|issue| issue.id == id).map(|issue| issue.kind);
+        assert!(matches!(kind(&missing), Some(IssueKind::MissingData)));
+        assert!(matches!(kind(&interrupted), Some(IssueKind::Interrupted)));
+        assert!(matches!(kind("orphan"), Some(IssueKind::OrphanData)));
+        assert!(issues.iter().all(|issue| issue.repaired.as_ref().is_some_and(|r| !r.starts_with("repair failed"))));
+
+        assert!(trash.remover.check(false).unwrap().is_empty());
+        // the interrupted removal is kept, the orphan is adopted
+        assert_eq!(trash.remover.restore(&interrupted, None).unwrap(), b);
+        assert!(trash.remover.open("orphan").is_ok());
+        assert!(trash.remover.open(&missing).is_err());
+    }
+
+    #[test]
+    fn rejects_ids_outside_the_trash() {
+        let trash = Trash::new();
+        let outside = trash.dir.path().join("outside");
+        fs::write(outside.with_extension("dat"), "data").unwrap();
+        fs::write(outside.with_extension("json"), r#""/tmp/x.png""#).unwrap();
+
+        for result in [trash.remover.purge("../outside", None), trash.remover.restore("../outside", None).map(|_| ())] {
+            let err = result.unwrap_err();
+            assert!(matches!(err.downcast_ref::<Error>(), Some(Error::TrashEntryNotFound(_))));
+        }
+        assert!(outside.with_extension("dat").is_file());
+    }
+
+    #[test]
+    fn restore_keeps_files_taking_the_original_place() {
+        let trash = Trash::new();
+        let (a, id) = trash.remove("a.png");
+        fs::write(&a, "new").unwrap();
+
+        let req = RestoreRequest { ids: vec![id.clone()], ..Default::default() };
+        let report = trash.remover.restore_selected(req).unwrap();
+        assert_eq!((report.restored, report.failed), (0, 1));
+        assert_eq!(report.errors.get("restore_conflict"), Some(&1));
+        assert_eq!(fs::read_to_string(&a).unwrap(), "new");
+
+        // the removed file stays in the trash
+        fs::remove_file(&a).unwrap();
+        assert_eq!(trash.remover.restore(&id, None).unwrap(), a);
+        assert_eq!(fs::read_to_string(&a).unwrap(), "a.png");
+    }
+
+    #[test]
+    fn removes_in_a_batch_reporting_each_file() {
+        let trash = Trash::new();
+        let a = trash.dir.path().join("a.png");
+        fs::write(&a, "a").unwrap();
+        let missing = trash.dir.path().join("missing.png");
+
+        let report = trash.remover.remove_all(vec![a.clone(), missing]);
+        assert_eq!((report.removed, report.failed), (1, 1));
+        assert_eq!(report.errors.get("file_not_found"), Some(&1));
+        assert!(report.files[0].id.is_some() && report.files[1].error.is_some());
+
+        trash.remover.undo(Selection::Batch(report.batch)).unwrap();
+        assert!(a.is_file());
+    }
+
+    #[test]
+    fn undoes_the_last_operations_skipping_purges() {
+        let trash = Trash::new();
+        let (a, id) = trash.remove("a.png");
+        let (b, _) = trash.remove("b.png");
+        trash.remover.purge(&id, None).unwrap();
+
+        let reverted = trash.remover.undo(Selection::Last(1)).unwrap();
+        assert_eq!(reverted.len(), 1);
+        assert!(reverted[0].error.is_none());
+        assert!(b.is_file());
+        assert!(!a.exists());
+
+        let redone = trash.remover.redo(Selection::Last(1)).unwrap();
+        assert!(redone[0].error.is_none());
+        assert!(!b.exists());
     }
 }
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::analyzer::{FileInfo, Group, Groups};
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// the number of images
+    Size,
+    /// the date of the newest image
+    Date,
+    /// the largest hash distance to the keeper
+    Distance,
+}
+
+/// a page of the analysis result, in the analysis order unless sorted;
+/// sorting is descending by default, all groups are returned if no limit is given
+#[derive(Debug, Default, Deserialize)]
+pub struct GroupQuery {
+    sort: Option<SortBy>,
+    #[serde(default)]
+    asc: bool,
+    #[serde(default)]
+    offset: usize,
+    limit: Option<usize>,
+}
+
+fn sort_key(group: &Group, sort: SortBy) -> u64 {
+    match sort {
+        SortBy::Size => group.files.len() as u64,
+        SortBy::Date => group.files.iter().map(|m| m.file.date).max().unwrap_or_default(),
+        SortBy::Distance => group.files.iter().filter_map(|m| m.dist).max().unwrap_or_default().into(),
+    }
+}
+
+/// the indices of the groups on the requested page
+pub fn page(groups: &Groups, query: &GroupQuery) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..groups.len()).collect();
+    if let Some(sort) = query.sort {
+        // the sort is stable, equal groups stay in the analysis order
+        if query.asc {
+            indices.sort_by_key(|&i| sort_key(&groups[i], sort));
+        } else {
+            indices.sort_by_key(|&i| Reverse(sort_key(&groups[i], sort)));
+        }
+    }
+
+    indices
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect()
+}
+
+/// a group as listed, with the space its duplicates take
+#[derive(Debug, Serialize)]
+pub struct GroupEntry {
+    #[serde(flatten)]
+    pub group: Group,
+    pub savings: u64,
+}
+
+impl GroupEntry {
+    pub fn new(group: &Group) -> Self {
+        Self { group: group.clone(), savings: group.savings() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderSavings {
+    pub folder: PathBuf,
+    pub duplicates: usize,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Summary {
+    pub groups: usize,
+    pub files: usize,
+    /// files besides the keeper of each group
+    pub duplicates: usize,
+    /// the size of the duplicates, reclaimed by removing them
+    pub duplicate_bytes: u64,
+    /// where the duplicates are, the most space first
+    pub folders: Vec<FolderSavings>,
+}
+
+/// the first folder below the analyzed root, the root itself for the files right in it
+fn top_folder(file: &FileInfo) -> PathBuf {
+    let parent = file.path.parent().map(PathBuf::from).unwrap_or_default();
+    let Some(root) = &file.root else {
+        return parent;
+    };
+
+    match file.path.strip_prefix(root).map(|rel| rel.components().collect::<Vec<_>>()) {
+        Ok(components) if components.len() > 1 => root.join(components[0]),
+        Ok(_) => root.clone(),
+        Err(_) => parent,
+    }
+}
+
+impl Summary {
+    pub fn new(groups: &Groups) -> Self {
+        let mut summary = Self { groups: groups.len(), files: 0, duplicates: 0, duplicate_bytes: 0, folders: Vec::new() };
+        let mut folders: HashMap<PathBuf, FolderSavings> = HashMap::new();
+        for group in groups {
+            for member in group.duplicates() {
+                summary.duplicates += 1;
+                summary.duplicate_bytes += member.file.size;
+
+                let folder = top_folder(&member.file);
+                let savings = folders
+                    .entry(folder.clone())
+                    .or_insert_with(|| FolderSavings { folder, duplicates: 0, bytes: 0 });
+                savings.duplicates += 1;
+                savings.bytes += member.file.size;
+            }
+            summary.files += group.files.len();
+        }
+
+        summary.folders = folders.into_values().collect();
+        summary.folders.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.folder.cmp(&b.folder)));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::Member;
+
+    /// a file in `/lib`, `name` may lie in a subfolder
+    fn member(name: &str, size: u64, date: u64, dist: Option<u32>) -> Member {
+        let file = FileInfo {
+            path: PathBuf::from("/lib").join(name),
+            size,
+            date,
+            modified: date,
+            reference: false,
+            root: Some("/lib".into()),
+            links: Vec::new(),
+        };
+        Member { file, score: None, dist }
+    }
+
+    fn groups() -> Groups {
+        vec![
+            Group { files: vec![member("a.png", 10, 1, Some(0)), member("x/a.png", 5, 2, Some(3))], status: None },
+            Group {
+                files: vec![member("b.png", 20, 5, Some(0)), member("x/b.png", 20, 6, Some(1)), member("y/b.png", 1, 3, None)],
+                status: None,
+            },
+            Group { files: vec![member("c.png", 7, 4, Some(0)), member("y/c.png", 7, 9, Some(2))], status: None },
+        ]
+    }
+
+    fn query(sort: Option<SortBy>, asc: bool, offset: usize, limit: Option<usize>) -> GroupQuery {
+        GroupQuery { sort, asc, offset, limit }
+    }
+
+    #[test]
+    fn pages_in_the_analysis_order() {
+        let groups = groups();
+        assert_eq!(page(&groups, &GroupQuery::default()), vec![0, 1, 2]);
+        assert_eq!(page(&groups, &query(None, false, 1, Some(1))), vec![1]);
+        assert!(page(&groups, &query(None, false, 5, None)).is_empty());
+    }
+
+    #[test]
+    fn sorts_descending_by_default_keeping_ties_in_order() {
+        let groups = groups();
+        assert_eq!(page(&groups, &query(Some(SortBy::Size), false, 0, None)), vec![1, 0, 2]);
+        assert_eq!(page(&groups, &query(Some(SortBy::Size), true, 0, None)), vec![0, 2, 1]);
+        assert_eq!(page(&groups, &query(Some(SortBy::Date), false, 0, Some(2))), vec![2, 1]);
+        assert_eq!(page(&groups, &query(Some(SortBy::Distance), false, 0, None)), vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn sums_the_duplicates_by_top_folder() {
+        let summary = Summary::new(&groups());
+        assert_eq!((summary.groups, summary.files, summary.duplicates), (3, 7, 4));
+        // the keeper of equally large files is the oldest one
+        assert_eq!(summary.duplicate_bytes, 5 + 20 + 1 + 7);
+
+        let folders: Vec<_> = summary.folders.iter().map(|f| (f.folder.clone(), f.duplicates, f.bytes)).collect();
+        assert_eq!(folders, vec![("/lib/x".into(), 2, 25), ("/lib/y".into(), 2, 8)]);
+    }
+}
@@ -0,0 +1,456 @@
+use eyre::Result;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Mutex};
+
+use crate::analyzer::FileInfo;
+use crate::error::Error;
+
+const EXTENSIONS: [&str; 3] = ["jpg", "jpeg", "png"];
+
+/// controls which folders are traversed and which files are listed
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ScanOptions {
+    /// glob patterns files must match, a pattern without slashes matches the file name,
+    /// otherwise the path relative to the root folder
+    pub include: Vec<String>,
+    /// glob patterns of files and folders to skip, matched the same way as `include`
+    pub exclude: Vec<String>,
+    /// how deep to descend into subfolders, 0 lists the root folder only
+    pub max_depth: Option<usize>,
+    /// list hidden (dot) files and folders as well
+    pub hidden: bool,
+    /// traverse symlinked folders and list symlinked files, on by default;
+    /// folders already visited through another path are skipped
+    pub follow_symlinks: bool,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            max_depth: None,
+            hidden: false,
+            follow_symlinks: true,
+            min_size: None,
+            max_size: None,
+            min_width: None,
+            min_height: None,
+            max_width: None,
+            max_height: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    fn has_dimensions(&self) -> bool {
+        self.min_width.is_some()
+            || self.min_height.is_some()
+            || self.max_width.is_some()
+            || self.max_height.is_some()
+    }
+}
+
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+}
+
+impl Patterns {
+    fn new(patterns: &[String]) -> Result<Self> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+
+        for pattern in patterns {
+            let glob = Glob::new(pattern)?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+
+        Ok(Self {
+            names: names.build()?,
+            paths: paths.build()?,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.names.is_empty() && self.paths.is_empty()
+    }
+
+    fn matches(&self, relative: &Path) -> bool {
+        let name = relative.file_name().is_some_and(|name| self.names.is_match(name));
+        name || self.paths.is_match(relative)
+    }
+}
+
+/// scan options compiled for traversal
+struct Filter<'a> {
+    options: &'a ScanOptions,
+    include: Patterns,
+    exclude: Patterns,
+}
+
+fn is_hidden(relative: &Path) -> bool {
+    relative
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path
+        .extension()
+        .is_some_and(|ext| EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+}
+
+impl<'a> Filter<'a> {
+    fn new(options: &'a ScanOptions) -> Result<Self> {
+        Ok(Self {
+            options,
+            include: Patterns::new(&options.include)?,
+            exclude: Patterns::new(&options.exclude)?,
+        })
+    }
+
+    fn accepts_dir(&self, relative: &Path, depth: usize) -> bool {
+        let deep = self.options.max_depth.is_some_and(|max| depth > max);
+        let hidden = !self.options.hidden && is_hidden(relative);
+        !deep && !hidden && !self.exclude.matches(relative)
+    }
+
+    fn accepts_path(&self, relative: &Path) -> bool {
+        let hidden = !self.options.hidden && is_hidden(relative);
+        let included = self.include.is_empty() || self.include.matches(relative);
+        is_image(relative) && !hidden && included && !self.exclude.matches(relative)
+    }
+
+    fn accepts_file(&self, file: &FileInfo) -> bool {
+        let options = self.options;
+        if options.min_size.is_some_and(|min| file.size < min)
+            || options.max_size.is_some_and(|max| file.size > max)
+        {
+            return false;
+        }
+
+        if !options.has_dimensions() {
+            return true;
+        }
+
+        // only the image header is read here
+        match image::image_dimensions(&file.path) {
+            Ok((width, height)) => {
+                !(options.min_width.is_some_and(|min| width < min)
+                    || options.min_height.is_some_and(|min| height < min)
+                    || options.max_width.is_some_and(|max| width > max)
+                    || options.max_height.is_some_and(|max| height > max))
+            }
+            Err(err) => {
+                tracing::error!(path = file.path.to_str(), "unable to read image dimensions: {:?}", err);
+                false
+            }
+        }
+    }
+}
+
+/// identifies a file across paths: hardlinks and symlinks resolve to the same id
+pub type FileId = (u64, u64);
+
+#[cfg(unix)]
+pub fn file_id(metadata: &fs::Metadata) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+pub fn file_id(_metadata: &fs::Metadata) -> Option<FileId> {
+    None
+}
+
+/// a folder to traverse, files found there are tagged with it
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub path: PathBuf,
+    /// the folder belongs to the reference collection
+    pub reference: bool,
+}
+
+/// other paths of already streamed files, keyed by the streamed path
+pub type Links = HashMap<PathBuf, Vec<PathBuf>>;
+
+/// folders are read concurrently, mostly waiting for the disk or network
+const SCAN_THREADS: usize = 8;
+
+struct Walker<'a> {
+    filter: Filter<'a>,
+    roots: &'a [Root],
+    files: mpsc::Sender<FileInfo>,
+    discovered: &'a AtomicUsize,
+    /// folders already traversed, protects from symlink cycles
+    visited: Mutex<HashSet<FileId>>,
+    /// paths of already streamed files
+    listed: Mutex<HashMap<FileId, PathBuf>>,
+    links: Mutex<Links>,
+}
+
+impl<'a> Walker<'a> {
+    /// returns false if the folder was already traversed
+    fn enter(&self, metadata: &fs::Metadata) -> bool {
+        file_id(metadata).is_none_or(|id| self.visited.lock().unwrap().insert(id))
+    }
+
+    fn add_file(&self, info: FileInfo, metadata: &fs::Metadata) {
+        if let Some(id) = file_id(metadata) {
+            let mut listed = self.listed.lock().unwrap();
+            if let Some(first) = listed.get(&id) {
+                // another name of an already listed file, removing it wouldn't free any space
+                let mut links = self.links.lock().unwrap();
+                links.entry(first.clone()).or_default().push(info.path);
+                return;
+            }
+            listed.insert(id, info.path.clone());
+        }
+
+        self.discovered.fetch_add(1, Ordering::Relaxed);
+        if self.files.send(info).is_err() {
+            tracing::error!("unable to stream the file, the receiver is gone");
+        }
+    }
+
+    fn walk_dir<'s>(&'s self, scope: &rayon::Scope<'s>, root: &'s Root, dir: PathBuf, depth: usize) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::error!("error reading folder content {:?}: {:?}", dir, err);
+                return;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Ok(relative) = path.strip_prefix(&root.path) else {
+                continue;
+            };
+
+            let is_symlink = entry.file_type().is_ok_and(|t| t.is_symlink());
+            if is_symlink && !self.filter.options.follow_symlinks {
+                continue;
+            }
+
+            // follows symlinks
+            let metadata = match fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    tracing::error!("unable to read metadata of {:?}: {:?}", path, err);
+                    continue;
+                }
+            };
+
+            if metadata.is_dir() {
+                // other roots are traversed on their own
+                if !self.filter.accepts_dir(relative, depth + 1)
+                    || self.roots.iter().any(|r| r.path == path)
+                {
+                    continue;
+                }
+                if !self.enter(&metadata) {
+                    tracing::warn!("skipping already visited folder {:?}", path);
+                    continue;
+                }
+                scope.spawn(move |scope| self.walk_dir(scope, root, path, depth + 1));
+            } else if self.filter.accepts_path(relative) {
+                let info = match FileInfo::from_metadata(path, &metadata) {
+                    Ok(info) => info,
+                    Err(err) => {
+                        tracing::error!("unable to read file info: {:?}", err);
+                        continue;
+                    }
+                };
+                if self.filter.accepts_file(&info) {
+                    let info = FileInfo {
+                        reference: root.reference,
+                        root: Some(root.path.clone()),
+                        ..info
+                    };
+                    self.add_file(info, &metadata);
+                }
+            }
+        }
+    }
+}
+
+/// traverses the roots in parallel, sending accepted files through `files` as soon
+/// as they are found; `discovered` counts the files sent so far.
+/// Returns other paths (links) of the sent files, found while traversing.
+pub fn scan(
+    roots: &[Root],
+    options: &ScanOptions,
+    files: mpsc::Sender<FileInfo>,
+    discovered: &AtomicUsize,
+) -> Result<Links> {
+    let walker = Walker {
+        filter: Filter::new(options)?,
+        roots,
+        files,
+        discovered,
+        visited: Mutex::new(HashSet::new()),
+        listed: Mutex::new(HashMap::new()),
+        links: Mutex::new(HashMap::new()),
+    };
+
+    let mut metadata = Vec::new();
+    for root in roots {
+        metadata.push(fs::metadata(&root.path).map_err(|err| Error::io(&root.path, err))?);
+    }
+
+    // a dedicated pool, so that traversal doesn't compete with hashing for threads
+    let pool = rayon::ThreadPoolBuilder::new().num_threads(SCAN_THREADS).build()?;
+    pool.scope(|scope| {
+        for (root, metadata) in roots.iter().zip(&metadata) {
+            if walker.enter(metadata) {
+                let walker = &walker;
+                scope.spawn(move |scope| walker.walk_dir(scope, root, root.path.clone(), 0));
+            }
+        }
+    });
+
+    Ok(walker.links.into_inner().unwrap())
+}
+
+/// moves the links found during traversal into the corresponding file
+pub fn attach_links(file: &mut FileInfo, links: &mut Links) {
+    if let Some(paths) = links.remove(&file.path) {
+        file.links = paths;
+    }
+}
+
+pub fn list_dir(dir: &Path, options: &ScanOptions) -> Result<Vec<FileInfo>> {
+    let roots = [Root { path: dir.to_owned(), reference: false }];
+    let (tx, rx) = mpsc::channel();
+    let mut links = scan(&roots, options, tx, &AtomicUsize::new(0))?;
+
+    let files = rx
+        .into_iter()
+        .map(|mut file| {
+            attach_links(&mut file, &mut links);
+            file
+        })
+        .collect();
+
+    Ok(files)
+}
+
+/// resolves symlinks in the given roots and drops the ones
+/// pointing into (or at) another root, so no folder is listed twice
+pub fn dedup_roots(roots: &[PathBuf]) -> Result<Vec<PathBuf>> {
+    let mut canonical = roots
+        .iter()
+        .map(|root| fs::canonicalize(root).map_err(|err| Error::io(root, err)))
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    // parents sort before their children
+    canonical.sort();
+
+    let mut result: Vec<PathBuf> = Vec::new();
+    for root in canonical {
+        if !result.iter().any(|r| root.starts_with(r)) {
+            result.push(root);
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    fn touch(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"").unwrap();
+    }
+
+    fn names(files: &[FileInfo]) -> Vec<String> {
+        let mut names: Vec<_> = files
+            .iter()
+            .map(|f| f.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn follows_symlinked_folders_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let (root, outside) = (dir.path().join("root"), dir.path().join("outside"));
+        touch(&root.join("a.png"));
+        touch(&outside.join("b.png"));
+        symlink(&outside, root.join("linked")).unwrap();
+
+        let files = list_dir(&root, &ScanOptions::default()).unwrap();
+        assert_eq!(names(&files), ["a.png", "b.png"]);
+
+        let options = ScanOptions { follow_symlinks: false, ..Default::default() };
+        assert_eq!(names(&list_dir(&root, &options).unwrap()), ["a.png"]);
+    }
+
+    #[test]
+    fn skips_symlink_cycles() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(&dir.path().join("sub/a.png"));
+        symlink(dir.path(), dir.path().join("sub/parent")).unwrap();
+
+        let files = list_dir(dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(names(&files), ["a.png"]);
+    }
+
+    #[test]
+    fn collapses_hardlinks() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(&dir.path().join("a.png"));
+        fs::hard_link(dir.path().join("a.png"), dir.path().join("b.png")).unwrap();
+
+        let files = list_dir(dir.path(), &ScanOptions::default()).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].links.len(), 1);
+    }
+
+    #[test]
+    fn filters_by_pattern_and_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["a.png", "b.jpg", "c.txt", "@eaDir/d.png", "sub/e.png", "sub/deep/f.png", ".hidden.png"] {
+            touch(&dir.path().join(name));
+        }
+
+        let options = ScanOptions { exclude: vec!["@eaDir".to_owned()], max_depth: Some(1), ..Default::default() };
+        assert_eq!(names(&list_dir(dir.path(), &options).unwrap()), ["a.png", "b.jpg", "e.png"]);
+
+        let options = ScanOptions { include: vec!["sub/**".to_owned()], ..Default::default() };
+        assert_eq!(names(&list_dir(dir.path(), &options).unwrap()), ["e.png", "f.png"]);
+    }
+
+    #[test]
+    fn drops_nested_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::create_dir_all(dir.path().join("c")).unwrap();
+
+        let roots = [dir.path().join("a/b"), dir.path().join("c"), dir.path().join("a")];
+        let base = fs::canonicalize(dir.path()).unwrap();
+        assert_eq!(dedup_roots(&roots).unwrap(), [base.join("a"), base.join("c")]);
+    }
+}
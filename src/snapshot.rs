@@ -0,0 +1,167 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::analyzer::{FileInfo, Group, GroupStatus, Similarity, Stamp};
+
+/// what is remembered about the last analysis with the same parameters,
+/// allows to compare only new or changed files on the next run
+#[derive(Debug)]
+pub struct Snapshot {
+    files: HashMap<PathBuf, Stamp>,
+    similarity: Similarity,
+    /// pairs of similar files
+    edges: Vec<(PathBuf, PathBuf)>,
+    groups: HashSet<BTreeSet<PathBuf>>,
+    /// files belonging to any of the groups
+    grouped: HashSet<PathBuf>,
+}
+
+/// snapshots kept for as many sets of parameters, the least recently used are dropped
+const MAX_SNAPSHOTS: usize = 16;
+
+/// the last analysis for every set of parameters, see `AnalyzeRequest::snapshot_key`
+#[derive(Debug, Default)]
+pub struct Snapshots {
+    /// the snapshots and when they were last used
+    entries: HashMap<String, (Arc<Snapshot>, u64)>,
+    clock: u64,
+}
+
+impl Snapshots {
+    pub fn get(&mut self, key: &str) -> Option<Arc<Snapshot>> {
+        self.clock += 1;
+        let (snapshot, used) = self.entries.get_mut(key)?;
+        *used = self.clock;
+        Some(snapshot.clone())
+    }
+
+    pub fn insert(&mut self, key: String, snapshot: Snapshot) {
+        self.clock += 1;
+        self.entries.insert(key, (Arc::new(snapshot), self.clock));
+
+        while self.entries.len() > MAX_SNAPSHOTS {
+            let oldest = self.entries.iter().min_by_key(|(_, (_, used))| *used).map(|(key, _)| key.clone());
+            if let Some(key) = oldest {
+                self.entries.remove(&key);
+            }
+        }
+    }
+}
+
+fn group_paths(group: &Group) -> BTreeSet<PathBuf> {
+    group.files.iter().map(|m| m.file.path.clone()).collect()
+}
+
+impl Snapshot {
+    pub fn new<'a, I>(files: I, similarity: Similarity, edges: Vec<(PathBuf, PathBuf)>, groups: &[Group]) -> Self
+    where
+        I: IntoIterator<Item = &'a FileInfo>,
+    {
+        let groups: HashSet<_> = groups.iter().map(group_paths).collect();
+        let grouped = groups.iter().flatten().cloned().collect();
+        Self {
+            files: files.into_iter().map(|f| (f.path.clone(), f.stamp())).collect(),
+            similarity,
+            edges,
+            groups,
+            grouped,
+        }
+    }
+
+    /// the file wasn't there last time or it has been modified since
+    pub fn is_fresh(&self, file: &FileInfo) -> bool {
+        self.files.get(&file.path) != Some(&file.stamp())
+    }
+
+    /// the parameters `edges` were found with
+    pub fn similarity(&self) -> &Similarity {
+        &self.similarity
+    }
+
+    pub fn edges(&self) -> &[(PathBuf, PathBuf)] {
+        &self.edges
+    }
+
+    /// the groups none of whose files are grouped anymore, as their paths
+    pub fn removed(&self, groups: &[Group]) -> Vec<BTreeSet<PathBuf>> {
+        let grouped: HashSet<&PathBuf> = groups.iter().flat_map(|g| &g.files).map(|m| &m.file.path).collect();
+        let mut removed: Vec<_> = self
+            .groups
+            .iter()
+            .filter(|paths| !paths.iter().any(|p| grouped.contains(p)))
+            .cloned()
+            .collect();
+        removed.sort();
+        removed
+    }
+
+    pub fn status(&self, group: &Group) -> GroupStatus {
+        let paths = group_paths(group);
+        if self.groups.contains(&paths) {
+            GroupStatus::Unchanged
+        } else if paths.iter().any(|p| self.grouped.contains(p)) {
+            GroupStatus::Changed
+        } else {
+            GroupStatus::New
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::{AnalyzeRequest, Member};
+
+    fn snapshot_of(groups: &[Group]) -> Snapshot {
+        let json = serde_json::json!({ "dist": 1, "paths": ["/photos"], "hashType": "DHash", "hashSize": 8 });
+        let req: AnalyzeRequest = serde_json::from_value(json).unwrap();
+        Snapshot::new([], req.similarity(), Vec::new(), groups)
+    }
+
+    fn snapshot() -> Snapshot {
+        snapshot_of(&[])
+    }
+
+    fn group(names: &[&str]) -> Group {
+        let member = |name: &&str| {
+            let file = FileInfo {
+                path: PathBuf::from("/photos").join(name),
+                size: 1,
+                date: 0,
+                modified: 0,
+                reference: false,
+                root: None,
+                links: Vec::new(),
+            };
+            Member { file, score: None, dist: None }
+        };
+        Group { files: names.iter().map(member).collect(), status: None }
+    }
+
+    #[test]
+    fn tells_group_changes() {
+        let snapshot = snapshot_of(&[group(&["a", "b"]), group(&["c", "d"]), group(&["e", "f"])]);
+        let current = [group(&["a", "b"]), group(&["c", "x"]), group(&["y", "z"])];
+
+        let status: Vec<_> = current.iter().map(|g| snapshot.status(g)).collect();
+        assert_eq!(status, [GroupStatus::Unchanged, GroupStatus::Changed, GroupStatus::New]);
+        let removed: BTreeSet<PathBuf> = ["/photos/e", "/photos/f"].map(PathBuf::from).into();
+        assert_eq!(snapshot.removed(&current), vec![removed]);
+    }
+
+    #[test]
+    fn drops_the_least_recently_used_snapshots() {
+        let mut snapshots = Snapshots::default();
+        for i in 0..MAX_SNAPSHOTS {
+            snapshots.insert(i.to_string(), snapshot());
+        }
+        assert!(snapshots.get("0").is_some());
+
+        snapshots.insert("new".to_owned(), snapshot());
+        assert_eq!(snapshots.entries.len(), MAX_SNAPSHOTS);
+        assert!(snapshots.get("0").is_some());
+        assert!(snapshots.get("1").is_none());
+        assert!(snapshots.get("new").is_some());
+    }
+}
@@ -0,0 +1,292 @@
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::{collections::{BTreeSet, HashSet}, fs, path::PathBuf, sync::{Arc, Mutex}, time::SystemTime};
+use uuid::Uuid;
+
+use crate::analyzer::{Analysis, AnalyzeRequest, Groups};
+use crate::error::Error;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum StoredResult {
+    Completed {
+        data: Groups,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        removed: Vec<BTreeSet<PathBuf>>,
+    },
+    Failed { error: String },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredTask {
+    pub id: Uuid,
+    pub request: AnalyzeRequest,
+    pub submitted: u64,
+    #[serde(default)]
+    pub completed: Option<u64>,
+    /// missing while the task is running, or if it was interrupted
+    #[serde(default)]
+    pub result: Option<StoredResult>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum TaskState {
+    Pending,
+    Completed,
+    Failed,
+}
+
+/// stored next to each task, so that tasks are listed without reading their results
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskSummary {
+    pub id: Uuid,
+    pub request: AnalyzeRequest,
+    pub submitted: u64,
+    pub completed: Option<u64>,
+    pub state: TaskState,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TaskSummary {
+    fn new(task: &StoredTask) -> Self {
+        let (state, groups, error) = match &task.result {
+            None => (TaskState::Pending, None, None),
+            Some(StoredResult::Completed { data, .. }) => (TaskState::Completed, Some(data.len()), None),
+            Some(StoredResult::Failed { error }) => (TaskState::Failed, None, Some(error.clone())),
+        };
+        Self {
+            id: task.id,
+            request: task.request.clone(),
+            submitted: task.submitted,
+            completed: task.completed,
+            state,
+            groups,
+            error,
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// keeps analysis requests and their results on disk,
+/// so they survive server restarts
+#[derive(Debug)]
+pub struct TaskStore {
+    root: PathBuf,
+    /// tasks submitted since the server started and not completed yet,
+    /// the other pending ones were interrupted
+    running: Mutex<HashSet<Uuid>>,
+}
+
+impl TaskStore {
+    pub fn new<T>(root: T) -> Self
+    where
+        PathBuf: From<T>
+    {
+        Self { root: PathBuf::from(root), running: Mutex::new(HashSet::new()) }
+    }
+
+    fn task_path(&self, id: &Uuid) -> PathBuf {
+        self.root.join(id.to_string()).with_extension("json")
+    }
+
+    fn summary_path(&self, id: &Uuid) -> PathBuf {
+        self.root.join(id.to_string()).with_extension("summary")
+    }
+
+    /// writes to a temporary file first, not to leave a truncated file behind
+    fn write_file(&self, path: PathBuf, content: Vec<u8>) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn write_summary(&self, summary: &TaskSummary) -> Result<()> {
+        self.write_file(self.summary_path(&summary.id), serde_json::to_vec(summary)?)
+    }
+
+    fn write(&self, task: &StoredTask) -> Result<()> {
+        self.write_file(self.task_path(&task.id), serde_json::to_vec(task)?)?;
+        // written last, a summary missing after a crash is rebuilt from the task
+        self.write_summary(&TaskSummary::new(task))
+    }
+
+    pub fn load(&self, id: &Uuid) -> Result<StoredTask> {
+        let content = fs::read(self.task_path(id))?;
+        let task = serde_json::from_slice(&content)?;
+        Ok(task)
+    }
+
+    /// remembers a submitted task, so it could be resumed if interrupted
+    pub fn save_request(&self, id: Uuid, request: AnalyzeRequest) -> Result<()> {
+        self.running.lock().unwrap().insert(id);
+        self.write(&StoredTask {
+            id,
+            request,
+            submitted: now(),
+            completed: None,
+            result: None,
+        })
+    }
+
+    pub fn save_result(&self, id: &Uuid, result: &Result<Arc<Analysis>>) -> Result<()> {
+        self.running.lock().unwrap().remove(id);
+        let mut task = self.load(id)?;
+        task.completed = Some(now());
+        task.result = Some(match result {
+            Ok(analysis) => StoredResult::Completed { data: analysis.groups.clone(), removed: analysis.removed.clone() },
+            Err(err) => StoredResult::Failed { error: err.to_string() },
+        });
+        self.write(&task)
+    }
+
+    /// returns the stored outcome of a task, interrupted tasks are reported as failed
+    pub fn load_result(&self, id: &Uuid) -> Option<Result<Analysis>> {
+        let task = self.load(id).ok()?;
+        Some(match task.result {
+            Some(StoredResult::Completed { data, removed }) => Ok(Analysis { groups: data, removed }),
+            Some(StoredResult::Failed { error }) => Err(Error::TaskFailed(*id, error).into()),
+            None => Err(Error::TaskInterrupted(*id).into()),
+        })
+    }
+
+    fn load_summary(&self, id: &Uuid) -> Result<TaskSummary> {
+        let content = fs::read(self.summary_path(id))?;
+        Ok(serde_json::from_slice(&content)?)
+    }
+
+    /// summaries of all stored tasks, the most recent first
+    fn summaries(&self) -> Result<Vec<TaskSummary>> {
+        let mut summaries = Vec::new();
+
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let id = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| Uuid::parse_str(stem).ok());
+            let Some(id) = id.filter(|_| path.extension().is_some_and(|ext| ext == "json")) else {
+                continue;
+            };
+
+            if let Ok(summary) = self.load_summary(&id) {
+                summaries.push(summary);
+                continue;
+            }
+
+            // stored before summaries were, or the server stopped in between
+            let summary = self.load(&id).map(|task| TaskSummary::new(&task)).and_then(|summary| {
+                self.write_summary(&summary)?;
+                Ok(summary)
+            });
+            match summary {
+                Ok(summary) => summaries.push(summary),
+                Err(err) => tracing::error!("unable to read stored task {:?}: {:?}", path, err),
+            }
+        }
+
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.submitted));
+        Ok(summaries)
+    }
+
+    /// pending tasks not running anymore are reported as failed, as polling them does
+    pub fn list_summaries(&self) -> Result<Vec<TaskSummary>> {
+        let running = self.running.lock().unwrap().clone();
+        let mut summaries = self.summaries()?;
+        for summary in &mut summaries {
+            if summary.state == TaskState::Pending && !running.contains(&summary.id) {
+                summary.state = TaskState::Failed;
+                summary.error = Some(Error::TaskInterrupted(summary.id).to_string());
+            }
+        }
+        Ok(summaries)
+    }
+
+    /// tasks submitted but never completed, e.g. because the server was stopped
+    pub fn interrupted(&self) -> Result<Vec<StoredTask>> {
+        let running = self.running.lock().unwrap().clone();
+        let tasks = self
+            .summaries()?
+            .into_iter()
+            .filter(|summary| summary.state == TaskState::Pending && !running.contains(&summary.id))
+            .filter_map(|summary| self.load(&summary.id).ok())
+            .collect();
+        Ok(tasks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> AnalyzeRequest {
+        let json = serde_json::json!({ "dist": 5, "paths": ["/photos"], "hashType": "DHash", "hashSize": 8 });
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn lists_tasks_from_summaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::new(dir.path());
+        let (done, failed) = (Uuid::new_v4(), Uuid::new_v4());
+        store.save_request(done, request()).unwrap();
+        store.save_request(failed, request()).unwrap();
+        store.save_result(&done, &Ok(Arc::default())).unwrap();
+        store.save_result(&failed, &Err(eyre::eyre!("broken"))).unwrap();
+
+        // the summaries are enough to list the tasks
+        fs::remove_file(store.task_path(&done)).unwrap();
+        fs::write(store.task_path(&done), b"").unwrap();
+
+        let summaries = store.list_summaries().unwrap();
+        let summary = |id| summaries.iter().find(|s| s.id == id).unwrap();
+        assert_eq!(summary(done).state, TaskState::Completed);
+        assert_eq!(summary(done).groups, Some(0));
+        assert_eq!(summary(failed).state, TaskState::Failed);
+        assert_eq!(summary(failed).error.as_deref(), Some("broken"));
+    }
+
+    #[test]
+    fn reports_interrupted_tasks_as_failed() {
+        let dir = tempfile::tempdir().unwrap();
+        let id = Uuid::new_v4();
+        TaskStore::new(dir.path()).save_request(id, request()).unwrap();
+
+        let running = TaskStore::new(dir.path());
+        running.save_request(Uuid::new_v4(), request()).unwrap();
+
+        // after a restart
+        let store = TaskStore::new(dir.path());
+        let summaries = store.list_summaries().unwrap();
+        assert!(summaries.iter().all(|s| s.state == TaskState::Failed));
+        assert!(summaries.iter().all(|s| s.error.as_deref() == Some("the task was interrupted")));
+        let error = store.load_result(&id).unwrap().unwrap_err();
+        assert!(matches!(error.downcast_ref::<Error>(), Some(Error::TaskInterrupted(_))));
+        assert_eq!(store.interrupted().unwrap().len(), 2);
+
+        // the running task is still pending until completed
+        let summaries = running.list_summaries().unwrap();
+        assert_eq!(summaries.iter().filter(|s| s.state == TaskState::Pending).count(), 1);
+    }
+
+    #[test]
+    fn rebuilds_missing_summaries() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = TaskStore::new(dir.path());
+        let id = Uuid::new_v4();
+        store.save_request(id, request()).unwrap();
+        store.save_result(&id, &Ok(Arc::default())).unwrap();
+        fs::remove_file(store.summary_path(&id)).unwrap();
+
+        assert_eq!(store.list_summaries().unwrap()[0].state, TaskState::Completed);
+        assert!(store.summary_path(&id).exists());
+    }
+}
@@ -0,0 +1,26 @@
+use eyre::Result;
+use image::{io::Reader, DynamicImage, ImageOutputFormat};
+use std::io::Cursor;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// the longest side of a thumbnail, in pixels
+pub const THUMBNAIL_SIZE: u32 = 160;
+
+const JPEG_QUALITY: u8 = 80;
+
+/// renders a JPEG preview of the image fitting into a `size`x`size` box;
+/// the format is told by the content, trashed files have no telling extension
+pub fn thumbnail(path: &Path, size: u32) -> Result<Vec<u8>> {
+    let reader = Reader::open(path)
+        .and_then(Reader::with_guessed_format)
+        .map_err(|err| Error::io(path, err))?;
+    let image = reader.decode().map_err(|err| Error::UnreadableImage(path.to_owned(), err))?;
+    // JPEG has no alpha channel
+    let preview = DynamicImage::ImageRgb8(image.thumbnail(size, size).to_rgb8());
+
+    let mut data = Cursor::new(Vec::new());
+    preview.write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))?;
+    Ok(data.into_inner())
+}
@@ -0,0 +1,150 @@
+use eyre::Result;
+use image::{imageops::FilterType, GrayImage};
+use rayon::prelude::*;
+use std::path::Path;
+
+use crate::analyzer::{FileInfo, Member};
+use crate::disjoint_set::DisjointSet;
+
+const THUMB_SIZE: u32 = 64;
+const WINDOW: u32 = 8;
+const STRIDE: u32 = 4;
+
+const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+fn load(path: &Path) -> Result<GrayImage> {
+    let image = image::open(path)?;
+    let thumb = image.resize_exact(THUMB_SIZE, THUMB_SIZE, FilterType::Triangle);
+    Ok(thumb.to_luma8())
+}
+
+fn window_ssim(a: &GrayImage, b: &GrayImage, x0: u32, y0: u32) -> f64 {
+    let n = (WINDOW * WINDOW) as f64;
+    let (mut sa, mut sb, mut saa, mut sbb, mut sab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+
+    for y in y0..y0 + WINDOW {
+        for x in x0..x0 + WINDOW {
+            let pa = a.get_pixel(x, y)[0] as f64;
+            let pb = b.get_pixel(x, y)[0] as f64;
+            sa += pa;
+            sb += pb;
+            saa += pa * pa;
+            sbb += pb * pb;
+            sab += pa * pb;
+        }
+    }
+
+    let (ma, mb) = (sa / n, sb / n);
+    let va = saa / n - ma * ma;
+    let vb = sbb / n - mb * mb;
+    let cov = sab / n - ma * mb;
+
+    ((2.0 * ma * mb + C1) * (2.0 * cov + C2)) / ((ma * ma + mb * mb + C1) * (va + vb + C2))
+}
+
+/// mean structural similarity of two equally sized thumbnails,
+/// computed over overlapping windows
+fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    let mut total = 0.0;
+    let mut count = 0;
+
+    for y in (0..=THUMB_SIZE - WINDOW).step_by(STRIDE as usize) {
+        for x in (0..=THUMB_SIZE - WINDOW).step_by(STRIDE as usize) {
+            total += window_ssim(a, b, x, y);
+            count += 1;
+        }
+    }
+
+    total / count as f64
+}
+
+/// re-checks a candidate group pixel by pixel, splitting it into
+/// sub-groups whose members are structurally similar to each other
+fn verify_group(group: Vec<FileInfo>, threshold: f64) -> Vec<Vec<Member>> {
+    let thumbs: Vec<_> = group
+        .par_iter()
+        .map(|file| match load(&file.path) {
+            Ok(thumb) => Some(thumb),
+            Err(err) => {
+                tracing::error!(path = file.path.to_str(), "unable to verify the image: {:?}", err);
+                None
+            }
+        })
+        .collect();
+
+    let n = group.len();
+    let mut scores = vec![0.0f64; n];
+    let mut ds = DisjointSet::new();
+
+    for i in 0..n {
+        ds.insert(i);
+    }
+
+    for i in 0..n {
+        for j in i + 1..n {
+            if let (Some(a), Some(b)) = (&thumbs[i], &thumbs[j]) {
+                let score = ssim(a, b);
+                if score >= threshold {
+                    ds.union(&i, &j);
+                    scores[i] = scores[i].max(score);
+                    scores[j] = scores[j].max(score);
+                }
+            }
+        }
+    }
+
+    let mut files: Vec<_> = group.into_iter().map(Some).collect();
+
+    ds
+        .into_vec()
+        .into_iter()
+        .filter(|v| v.len() > 1)
+        .map(|v| {
+            v
+                .into_iter()
+                .filter_map(|i| {
+                    let file = files[i].take()?;
+                    Some(Member { file, score: Some(scores[i]), dist: None })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+pub fn verify_groups(groups: Vec<Vec<FileInfo>>, threshold: f64) -> Vec<Vec<Member>> {
+    groups
+        .into_iter()
+        .flat_map(|group| verify_group(group, threshold))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    fn gradient(invert: bool) -> GrayImage {
+        GrayImage::from_fn(THUMB_SIZE, THUMB_SIZE, |x, y| {
+            let v = ((x * 3 + y) % 256) as u8;
+            Luma([if invert { 255 - v } else { v }])
+        })
+    }
+
+    #[test]
+    fn identical_images_are_fully_similar() {
+        let a = gradient(false);
+        assert!((ssim(&a, &a) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn inverted_images_are_dissimilar() {
+        assert!(ssim(&gradient(false), &gradient(true)) < 0.5);
+    }
+
+    #[test]
+    fn similarity_is_symmetric() {
+        let (a, b) = (gradient(false), gradient(true));
+        assert!((ssim(&a, &b) - ssim(&b, &a)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,156 @@
+use eyre::Result;
+use notify::{
+    event::{AccessKind, AccessMode, ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use tokio::sync::broadcast;
+
+use crate::analyzer::{Analyzer, FileInfo, HashType, SearchMatch};
+use crate::scanner::{self, ScanOptions};
+
+/// the folders to watch and how to hash their images
+#[derive(Debug, Clone)]
+pub struct WatchConfig {
+    pub roots: Vec<PathBuf>,
+    pub hash_type: HashType,
+    pub hash_size: u32,
+    /// max distance to report a new image as a duplicate
+    pub max_dist: u32,
+}
+
+impl WatchConfig {
+    /// reads folders separated like PATH from `IMAGE_ANALYZER_WATCH`, None if unset;
+    /// the hash parameters default to the ones the client uses
+    pub fn from_env() -> Result<Option<Self>> {
+        let Some(paths) = std::env::var_os("IMAGE_ANALYZER_WATCH") else {
+            return Ok(None);
+        };
+        let var = |name| std::env::var(name).ok().map(|value| value.parse::<u32>()).transpose();
+
+        let hash_type = match std::env::var("IMAGE_ANALYZER_WATCH_HASH_TYPE") {
+            Ok(name) => serde_json::from_value(serde_json::Value::String(name))?,
+            Err(_) => HashType::DHash,
+        };
+
+        Ok(Some(Self {
+            roots: std::env::split_paths(&paths).collect(),
+            hash_type,
+            hash_size: var("IMAGE_ANALYZER_WATCH_HASH_SIZE")?.unwrap_or(8),
+            max_dist: var("IMAGE_ANALYZER_WATCH_DIST")?.unwrap_or(5),
+        }))
+    }
+}
+
+/// sent to subscribers when a new or modified image duplicates an already known one
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct DuplicateFound {
+    pub file: FileInfo,
+    pub duplicates: Vec<SearchMatch>,
+}
+
+fn is_image(path: &Path) -> bool {
+    scanner::is_image(path) && path.is_file()
+}
+
+fn index(engine: &Analyzer, config: &WatchConfig, path: &Path, events: &broadcast::Sender<DuplicateFound>) {
+    if !is_image(path) {
+        return;
+    }
+
+    match engine.index_file(path, config.hash_type, config.hash_size, config.max_dist) {
+        Ok(duplicates) if !duplicates.is_empty() => {
+            let Ok(file) = FileInfo::from_path(path) else {
+                return;
+            };
+            tracing::info!(path = path.to_str(), "duplicate found: {:?}", duplicates);
+            // nobody listening is fine
+            let _ = events.send(DuplicateFound { file, duplicates });
+        }
+        Ok(_) => {}
+        Err(err) => tracing::error!(path = path.to_str(), "unable to index the image: {:?}", err),
+    }
+}
+
+fn evict(engine: &Analyzer, path: &Path) {
+    if let Err(err) = engine.evict(path) {
+        tracing::error!(path = path.to_str(), "unable to evict the image: {:?}", err);
+    }
+}
+
+fn handle_event(engine: &Analyzer, config: &WatchConfig, event: Event, events: &broadcast::Sender<DuplicateFound>) {
+    match event.kind {
+        EventKind::Create(_)
+        | EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any)
+        | EventKind::Modify(ModifyKind::Name(RenameMode::To))
+        | EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
+            for path in &event.paths {
+                index(engine, config, path, events);
+            }
+        }
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            for path in &event.paths {
+                evict(engine, path);
+            }
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
+            if let [from, to] = &event.paths[..] {
+                evict(engine, from);
+                index(engine, config, to, events);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// hashes everything already there, so new images can be compared to it;
+/// duplicates among these are left to the analysis
+fn index_roots(engine: &Analyzer, config: &WatchConfig) {
+    for root in &config.roots {
+        let files = match scanner::list_dir(root, &ScanOptions::default()) {
+            Ok(files) => files,
+            Err(err) => {
+                tracing::error!("unable to list watched folder {:?}: {:?}", root, err);
+                continue;
+            }
+        };
+
+        files.par_iter().for_each(|file| {
+            if let Err(err) = engine.hash_file(&file.path, config.hash_type, config.hash_size) {
+                tracing::error!(path = file.path.to_str(), "unable to index the image: {:?}", err);
+            }
+        });
+    }
+}
+
+/// keeps hashes of the images under `roots` up to date in the background
+pub fn spawn(engine: Arc<Analyzer>, mut config: WatchConfig, events: broadcast::Sender<DuplicateFound>) -> Result<()> {
+    // the same paths the analysis caches hashes under
+    config.roots = scanner::dedup_roots(&config.roots)?;
+
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx)?;
+
+    for root in &config.roots {
+        tracing::info!("watching {:?}", root);
+        watcher.watch(root, RecursiveMode::Recursive)?;
+    }
+
+    thread::spawn(move || {
+        // owned by the thread to keep watching for as long as it runs
+        let _watcher = watcher;
+        index_roots(&engine, &config);
+
+        for result in rx {
+            match result {
+                Ok(event) => handle_event(&engine, &config, event, &events),
+                Err(err) => tracing::error!("watch error: {:?}", err),
+            }
+        }
+    });
+
+    Ok(())
+}